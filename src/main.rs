@@ -1,9 +1,30 @@
 mod utils;
 
+use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
-use utils::file_writing::write_to_file;
+use std::fs;
+use std::path::PathBuf;
+use utils::ask::ask;
+use utils::categorize::suggest_category;
+use utils::config::Config;
+use utils::dedupe::{self, add_with_dedupe, DedupePlan};
+use utils::digest::{self, GroupBy};
+use utils::doctor::run_doctor;
+use utils::input;
+use utils::entities::{entity_counts, extract_entities};
+use utils::keywords::tag_notes;
+use utils::metadata::{EntityKind, MetadataStore};
+use utils::models::{
+    install_model, load_keyword_model, load_ner_model, load_question_answering_model, load_summarization_model,
+    ModelKind,
+};
+use utils::notes::{self, Note, NoteFilter};
+use utils::output::{print_results, OutputFormat};
+use utils::query::Query;
+use utils::search::{search_notes, SearchMode};
 use utils::viewer::view_notes;
 use utils::semantic_search::SemanticSearch;
+use utils::topics::find_topics;
 
 #[derive(Parser)]
 #[command(name = "pad", about = "A notepad for quick thoughts")]
@@ -32,27 +53,219 @@ enum Commands {
         #[arg(short = 'i', long = "idea")]
         idea: bool,
 
-        /// The text content to be saved
-        text: String,
+        /// Assign the suggested category when no category flag is given
+        #[arg(long = "auto")]
+        auto: bool,
+
+        /// Add the note even if a near-duplicate already exists
+        #[arg(long = "force")]
+        force: bool,
+
+        /// The text content to be saved; `-` reads from stdin, and leaving
+        /// it out opens $EDITOR
+        text: Option<String>,
     },
     /// View all notes
-    View,
-    /// Search notes semantically
+    View {
+        #[command(flatten)]
+        filter: NoteFilter,
+    },
+    /// Find notes similar to an existing note
+    Related {
+        /// ID of the note (see `pad list`)
+        id: usize,
+
+        /// Number of results to return
+        #[arg(short = 'k', long = "k-results", default_value = "5")]
+        k: usize,
+
+        /// Output format
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Find clusters of duplicate notes and merge them
+    Dedupe {
+        /// Cosine similarity at which notes count as duplicates (defaults to
+        /// the dedupe threshold in the config)
+        #[arg(long = "threshold")]
+        threshold: Option<f32>,
+
+        /// Only list the clusters
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Group notes into topics by meaning
+    Topics {
+        /// Number of topics (picked automatically when left out)
+        #[arg(short = 'k', long = "topics")]
+        k: Option<usize>,
+
+        #[command(flatten)]
+        filter: NoteFilter,
+    },
+    /// Summarize recent notes as markdown
+    Digest {
+        /// Include notes on or after this date (YYYY-MM-DD, or 7d/2w/3m ago)
+        #[arg(long = "since", default_value = "7d", value_parser = notes::parse_date)]
+        since: NaiveDate,
+
+        /// Only include notes in this category
+        #[arg(short = 'c', long = "category")]
+        category: Option<String>,
+
+        /// How to split the digest into sections
+        #[arg(long = "group-by", value_enum, default_value_t = GroupBy::Category)]
+        group_by: GroupBy,
+
+        /// Write the digest to this file instead of stdout
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+    },
+    /// Answer a question from your notes
+    Ask {
+        /// The question
+        question: String,
+
+        /// Number of notes to read for the answer
+        #[arg(short = 'k', long = "k-results", default_value = "5")]
+        k: usize,
+    },
+    /// Extract auto-tags for notes that don't have them yet
+    Autotag {
+        /// Re-extract auto-tags for every note
+        #[arg(long = "all")]
+        all: bool,
+    },
+    /// Find people, organizations and places in notes that haven't been processed yet
+    ExtractEntities {
+        /// Re-run extraction on every note
+        #[arg(long = "all")]
+        all: bool,
+    },
+    /// List the people mentioned in your notes
+    People,
+    /// List notes mentioning an entity, or every entity when no name is given
+    Entities {
+        /// Person, organization or place to look up
+        name: Option<String>,
+    },
+    /// Print notes with their IDs
+    List {
+        /// Only show notes matching this query, e.g. 'tag:rust -draft'
+        #[arg(short = 'f', long = "filter")]
+        filter: Option<String>,
+
+        #[command(flatten)]
+        note_filter: NoteFilter,
+    },
+    /// Delete notes by ID or filter
+    Rm {
+        /// IDs of the notes to delete (see `pad list`)
+        #[arg(required_unless_present = "filter")]
+        ids: Vec<usize>,
+
+        /// Delete every note matching this query
+        #[arg(short = 'f', long = "filter")]
+        filter: Option<String>,
+
+        /// Show what would be deleted without changing anything
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Move notes to another category
+    Mv {
+        /// IDs of the notes to move (see `pad list`)
+        #[arg(required_unless_present = "filter")]
+        ids: Vec<usize>,
+
+        /// The category to move the notes to
+        #[arg(short = 'c', long = "category")]
+        category: String,
+
+        /// Move every note matching this query
+        #[arg(short = 'f', long = "filter")]
+        filter: Option<String>,
+
+        /// Show what would be moved without changing anything
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Search notes by keywords and meaning
     Search {
-        /// The search query
+        /// The search query, e.g. 'async runtime category:idea -draft'
         query: String,
         
         /// Number of results to return
         #[arg(short = 'k', long = "k-results", default_value = "5")]
         k: usize,
+
+        /// Ranking to use
+        #[arg(short = 'm', long = "mode", value_enum, default_value_t = SearchMode::Hybrid)]
+        mode: SearchMode,
+
+        /// Output format
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Drop results scoring below this value: BM25 for lexical, cosine
+        /// similarity for semantic, 0 to 1 for hybrid
+        #[arg(long = "min-score")]
+        min_score: Option<f32>,
+
+        #[command(flatten)]
+        filter: NoteFilter,
+    },
+    /// Reconcile the embeddings with the notes file
+    Reindex {
+        /// Embed every note again from scratch
+        #[arg(long = "full", conflicts_with = "missing")]
+        full: bool,
+        /// Only embed notes without an embedding and drop orphaned ones (the default)
+        #[arg(long = "missing")]
+        missing: bool,
+    },
+    /// Check notes, embeddings, index, model and permissions for problems
+    Doctor {
+        /// Fix the problems that can be fixed automatically
+        #[arg(long = "repair")]
+        repair: bool,
+    },
+    /// Manage local models
+    Model {
+        #[command(subcommand)]
+        command: ModelCommand,
+    },
+    /// Inspect the semantic search index
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelCommand {
+    /// Copy a downloaded model into place and check that it loads
+    Install {
+        /// Which model the files are for
+        #[arg(value_enum, default_value_t = ModelKind::Embeddings)]
+        kind: ModelKind,
+        /// Directory holding the model files
+        #[arg(long = "from")]
+        from: PathBuf,
     },
 }
 
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Show the size of the index and its recall against an exact search
+    Stats,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Add { read, watch, listen, idea, text } => {
+        Commands::Add { read, watch, listen, idea, auto, force, text } => {
             let category = if read {
                 "read"
             } else if watch {
@@ -64,25 +277,336 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             } else {
                 "general"
             };
-            write_to_file(Some(category), &text)?;
+            let input = match text.as_deref() {
+                Some("-") => input::read_stdin()?,
+                Some(text) => input::NoteInput { text: text.to_string(), category: None },
+                None => input::read_from_editor(category)?,
+            };
+            if input.text.trim().is_empty() {
+                println!("Empty note, nothing saved");
+                return Ok(());
+            }
+            let explicit_category = read || watch || listen || idea || input.category.is_some();
+            let mut category = input.category.as_deref().unwrap_or(category).to_string();
+
+            let config = Config::load()?;
+            let threshold = (config.dedupe.enabled && !force).then_some(config.dedupe.threshold);
+            let mut semantic_search = SemanticSearch::new()?;
+            let embedding = semantic_search.embed(&input.text)?;
+
+            if !explicit_category {
+                let all_notes = notes::load_notes()?;
+                let suggestion = suggest_category(&semantic_search, &all_notes, &embedding, config.categorize.threshold);
+                match suggestion {
+                    Some(suggestion) if auto => {
+                        println!("Categorized as [{}] (confidence {:.2})", suggestion.category, suggestion.confidence);
+                        category = suggestion.category;
+                    }
+                    Some(suggestion) => println!(
+                        "Suggested category: [{}] (confidence {:.2}); pass --auto to apply suggestions",
+                        suggestion.category, suggestion.confidence
+                    ),
+                    None => {}
+                }
+            }
             
-            // Add to semantic search index
+            // Add to notes and the semantic search index
+            if let Some(outcome) = add_with_dedupe(&mut semantic_search, &category, &input.text, embedding, threshold)? {
+                println!("{}", outcome);
+            }
+
+            if config.autotag.on_add {
+                let untagged: Vec<Note> = notes::load_notes()?
+                    .into_iter()
+                    .filter(|note| note.text == input.text && note.auto_tags.is_empty())
+                    .take(1)
+                    .collect();
+                if !untagged.is_empty() {
+                    let mut store = MetadataStore::load()?;
+                    let model = load_keyword_model(&config)?;
+                    tag_notes(&model, &config.autotag, &untagged, &mut store)?;
+                    store.save()?;
+                }
+            }
+
+            if config.entities.on_add {
+                let unprocessed: Vec<Note> = notes::load_notes()?
+                    .into_iter()
+                    .filter(|note| note.text == input.text && note.entities.is_empty())
+                    .take(1)
+                    .collect();
+                if !unprocessed.is_empty() {
+                    let mut store = MetadataStore::load()?;
+                    let model = load_ner_model(&config)?;
+                    extract_entities(&model, &config.entities, &unprocessed, &mut store);
+                    store.save()?;
+                }
+            }
+        }
+        Commands::View { filter } => {
+            view_notes(&filter)?;
+        }
+        Commands::Related { id, k, format } => {
+            let all_notes = notes::load_notes()?;
+            let note = notes::select_notes(&all_notes, &[id], None)?[0];
+            let semantic_search = SemanticSearch::new()?;
+            let results = semantic_search.related(note, k)?;
+            print_results(&format!("Notes related to #{}: '{}'", note.id, note.text), &results, format)?;
+        }
+        Commands::Dedupe { threshold, dry_run } => {
+            let threshold = match threshold {
+                Some(threshold) => threshold,
+                None => Config::load()?.dedupe.threshold,
+            };
             let mut semantic_search = SemanticSearch::new()?;
-            semantic_search.add_note(&text)?;
+            let all_notes = notes::load_notes()?;
+            let clusters = dedupe::find_clusters(&semantic_search, &all_notes, threshold);
+            if clusters.is_empty() {
+                println!("No duplicates found");
+                return Ok(());
+            }
+
+            let mut plan = DedupePlan::default();
+            for (i, cluster) in clusters.iter().enumerate() {
+                println!("\nCluster {} of {}:", i + 1, clusters.len());
+                if dry_run {
+                    cluster.iter().for_each(print_note);
+                } else if !dedupe::review_cluster(cluster, &mut plan)? {
+                    break;
+                }
+            }
+            if !dry_run {
+                let removed = dedupe::apply_plan(&mut semantic_search, &plan)?;
+                println!("Removed {} duplicate note(s), merged {}", removed, plan.replace.len());
+            }
+        }
+        Commands::Topics { k, filter } => {
+            let semantic_search = SemanticSearch::new()?;
+            let mut all_notes = notes::load_notes()?;
+            all_notes.retain(|note| filter.matches(note));
+            for topic in find_topics(&semantic_search, &all_notes, k) {
+                println!("\n## {} ({} notes)", topic.label, topic.notes.len());
+                topic.notes.iter().for_each(print_note);
+            }
         }
-        Commands::View => {
-            view_notes()?;
+        Commands::Digest { since, category, group_by, output } => {
+            let filter = NoteFilter { category, since: Some(since), ..NoteFilter::default() };
+            let mut recent = notes::load_notes()?;
+            recent.retain(|note| filter.matches(note));
+
+            let config = Config::load()?;
+            let model = load_summarization_model(&config)?;
+            let semantic_search = match group_by {
+                GroupBy::Topic => Some(SemanticSearch::new()?),
+                GroupBy::Category => None,
+            };
+            let sections = digest::group_notes(&recent, group_by, semantic_search.as_ref());
+            let markdown = digest::build_digest(&model, &sections, since)?;
+            match output {
+                Some(path) => fs::write(&path, markdown)?,
+                None => print!("{}", markdown),
+            }
         }
-        Commands::Search { query, k } => {
+        Commands::Ask { question, k } => {
+            let config = Config::load()?;
+            let model = load_question_answering_model(&config)?;
             let semantic_search = SemanticSearch::new()?;
-            let results = semantic_search.search(&query, k)?;
-            
-            println!("\nSemantic search results for: '{}'", query);
-            println!("----------------------------------------");
-            for (i, (text, distance)) in results.iter().enumerate() {
-                println!("{}. {} (distance: {:.4})", i + 1, text, distance);
+            let answers = ask(&semantic_search, &model, &question, k)?;
+
+            let Some(best) = answers.first() else {
+                println!("No notes found to answer from");
+                return Ok(());
+            };
+            println!("{} (confidence {:.2})", best.answer.trim(), best.score);
+            println!("  from #{} [{}] {}", best.source.note.id, best.source.note.date, best.source.note.text);
+            if answers.len() > 1 {
+                println!("\nOther candidates:");
+                for answer in &answers[1..] {
+                    println!(
+                        "  {} (confidence {:.2}, #{} [{}])",
+                        answer.answer.trim(), answer.score, answer.source.note.id, answer.source.note.date
+                    );
+                }
+            }
+        }
+        Commands::Autotag { all } => {
+            let config = Config::load()?;
+            let all_notes = notes::load_notes()?;
+            let mut store = MetadataStore::load()?;
+            // Forget notes that no longer exist
+            store.entries.retain(|text, _| all_notes.iter().any(|note| &note.text == text));
+
+            let pending: Vec<Note> = all_notes
+                .into_iter()
+                .filter(|note| all || store.get(&note.text).is_none_or(|meta| meta.auto_tags.is_none()))
+                .collect();
+            if !pending.is_empty() {
+                let model = load_keyword_model(&config)?;
+                tag_notes(&model, &config.autotag, &pending, &mut store)?;
+            }
+            store.save()?;
+            println!("Auto-tagged {} note(s)", pending.len());
+        }
+        Commands::ExtractEntities { all } => {
+            let config = Config::load()?;
+            let all_notes = notes::load_notes()?;
+            let mut store = MetadataStore::load()?;
+            store.entries.retain(|text, _| all_notes.iter().any(|note| &note.text == text));
+
+            let pending: Vec<Note> = all_notes
+                .into_iter()
+                .filter(|note| all || store.get(&note.text).is_none_or(|meta| meta.entities.is_none()))
+                .collect();
+            if !pending.is_empty() {
+                let model = load_ner_model(&config)?;
+                extract_entities(&model, &config.entities, &pending, &mut store);
+            }
+            store.save()?;
+            println!("Extracted entities from {} note(s)", pending.len());
+        }
+        Commands::People => {
+            let all_notes = notes::load_notes()?;
+            for (person, count) in entity_counts(&all_notes, &[EntityKind::Person]) {
+                println!("{:>4}  {}", count, person.name);
+            }
+        }
+        Commands::Entities { name } => {
+            let all_notes = notes::load_notes()?;
+            match name {
+                Some(name) => {
+                    all_notes.iter().filter(|note| note.mentions(&name)).for_each(print_note);
+                }
+                None => {
+                    let kinds = [EntityKind::Person, EntityKind::Organization, EntityKind::Location];
+                    for (entity, count) in entity_counts(&all_notes, &kinds) {
+                        println!("{:>4}  {:<14} {}", count, entity.kind.label(), entity.name);
+                    }
+                }
+            }
+        }
+        Commands::List { filter, note_filter } => {
+            let query = filter.as_deref().map(Query::parse).transpose()?;
+            for note in notes::load_notes()? {
+                if query.as_ref().is_none_or(|q| q.matches(&note)) && note_filter.matches(&note) {
+                    print_note(&note);
+                }
+            }
+        }
+        Commands::Rm { ids, filter, dry_run } => {
+            let all_notes = notes::load_notes()?;
+            let query = filter.as_deref().map(Query::parse).transpose()?;
+            let targets: Vec<usize> = notes::select_notes(&all_notes, &ids, query.as_ref())?
+                .into_iter()
+                .map(|note| note.id)
+                .collect();
+            if dry_run {
+                println!("Would delete {} note(s):", targets.len());
+                all_notes.iter().filter(|n| targets.contains(&n.id)).for_each(print_note);
+                return Ok(());
+            }
+
+            let removed = notes::delete_notes(&targets)?;
+            let remaining = notes::load_notes()?;
+            // Identical text may still belong to another note, so only drop
+            // embeddings that no remaining note uses
+            let orphaned: Vec<String> = removed
+                .iter()
+                .map(|note| note.text.clone())
+                .filter(|text| !remaining.iter().any(|n| &n.text == text))
+                .collect();
+            if !orphaned.is_empty() {
+                let mut semantic_search = SemanticSearch::new()?;
+                semantic_search.remove_notes(&orphaned)?;
+            }
+            println!("Deleted {} note(s)", removed.len());
+        }
+        Commands::Mv { ids, category, filter, dry_run } => {
+            notes::check_category(&category)?;
+            let all_notes = notes::load_notes()?;
+            let query = filter.as_deref().map(Query::parse).transpose()?;
+            let targets: Vec<usize> = notes::select_notes(&all_notes, &ids, query.as_ref())?
+                .into_iter()
+                .map(|note| note.id)
+                .collect();
+            if dry_run {
+                println!("Would move {} note(s) to [{}]:", targets.len(), category);
+                all_notes.iter().filter(|n| targets.contains(&n.id)).for_each(print_note);
+                return Ok(());
+            }
+
+            // Embeddings are keyed by text only, so a category change leaves
+            // them untouched
+            let moved = notes::move_notes(&targets, &category)?;
+            println!("Moved {} note(s) to [{}]", moved, category);
+        }
+        Commands::Search { query, k, mode, format, min_score, filter } => {
+            // Lexical search doesn't need the model, so skip loading it
+            let semantic_search = match mode {
+                SearchMode::Lexical => None,
+                _ => Some(SemanticSearch::new()?),
+            };
+            let mut results = search_notes(semantic_search.as_ref(), &query, k, &filter, mode)?;
+            if let Some(min_score) = min_score {
+                results.retain(|result| result.score >= min_score);
+            }
+            print_results(&format!("Search results for: '{}'", query), &results, format)?;
+        }
+        Commands::Reindex { full, missing: _ } => {
+            let all_notes = notes::load_notes()?;
+            let mut semantic_search = SemanticSearch::new()?;
+            let report = semantic_search.reindex(&all_notes, full)?;
+            println!(
+                "Embedded {} note(s), dropped {} orphaned embedding(s)",
+                report.embedded, report.removed
+            );
+        }
+        Commands::Doctor { repair } => {
+            if run_doctor(repair)? > 0 {
+                std::process::exit(1);
+            }
+        }
+        Commands::Model { command: ModelCommand::Install { kind, from } } => {
+            let config = Config::load()?;
+            let dir = install_model(kind, &from, &config)?;
+            println!("Installed the {} model in {}", kind.key(), dir.display());
+        }
+        Commands::Index { command: IndexCommand::Stats } => {
+            let semantic_search = SemanticSearch::new()?;
+            let stats = semantic_search.stats();
+            println!("Embeddings:    {} ({} dimensions, {})", stats.embeddings, stats.dimension, stats.model_id);
+            println!("Store on disk: {} ({} deleted rows awaiting compaction)", format_bytes(stats.store_bytes), stats.dead_rows);
+            println!(
+                "HNSW graph:    m={}, ef_construction={}, ef_search={}, capacity {}",
+                stats.params.m, stats.params.ef_construction, stats.ef_search, stats.params.capacity
+            );
+            println!("Quantization:  {} (rescoring {}x candidates)", stats.quantization.label(), stats.rescore_factor);
+            println!("\nRecall@10 against exact search over {} sample queries:", stats.sample);
+            for method in &stats.methods {
+                println!("  {:<8} {:>10}  {:>6.1}%", method.name, format_bytes(method.vector_bytes as u64), method.recall * 100.0);
+            }
+            match &stats.cache {
+                Some(cache) => {
+                    println!(
+                        "\nEmbedding cache: {} of {} entries ({}), {:.1}% hit rate ({} hits, {} misses)",
+                        cache.entries, cache.max_entries, format_bytes(cache.bytes),
+                        cache.hit_rate() * 100.0, cache.hits, cache.misses
+                    );
+                }
+                None => println!("\nEmbedding cache: disabled"),
             }
         }
     }
     Ok(())
 }
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+fn print_note(note: &Note) {
+    println!("{:>4}  {}", note.id, note.to_line());
+}
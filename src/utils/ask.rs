@@ -0,0 +1,46 @@
+use rust_bert::pipelines::question_answering::{QaInput, QuestionAnsweringModel};
+use crate::utils::semantic_search::{SearchError, SearchResult, SemanticSearch};
+
+/// An answer span extracted from one of the retrieved notes.
+#[derive(Clone, Debug)]
+pub struct NoteAnswer {
+    pub answer: String,
+    pub score: f64,
+    pub source: SearchResult,
+}
+
+/// Retrieve the `k` notes closest to the question and run extractive QA over
+/// each of them, returning answers best first.
+pub fn ask(
+    semantic_search: &SemanticSearch,
+    model: &QuestionAnsweringModel,
+    question: &str,
+    k: usize,
+) -> Result<Vec<NoteAnswer>, SearchError> {
+    let sources = semantic_search.search(question, k)?;
+    if sources.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // One input per note, so every answer maps back to a single source
+    let inputs: Vec<QaInput> = sources
+        .iter()
+        .map(|source| QaInput {
+            question: question.to_string(),
+            context: source.note.text.clone(),
+        })
+        .collect();
+    let predictions = model.predict(&inputs, 1, 8);
+
+    let mut answers: Vec<NoteAnswer> = predictions
+        .into_iter()
+        .zip(sources)
+        .filter_map(|(mut answers, source)| {
+            let best = answers.drain(..).next()?;
+            Some(NoteAnswer { answer: best.answer, score: best.score, source })
+        })
+        .filter(|answer| !answer.answer.trim().is_empty())
+        .collect();
+    answers.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(answers)
+}
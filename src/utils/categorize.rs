@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use crate::utils::notes::Note;
+use crate::utils::semantic_search::SemanticSearch;
+
+// Categories with fewer example notes than this are too noisy to suggest
+const MIN_EXAMPLES: usize = 3;
+
+/// A suggested category for a new note.
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    pub category: String,
+    /// Cosine similarity between the note and the category centroid
+    pub confidence: f32,
+}
+
+/// Suggest a category for `embedding` by comparing it with the centroid of
+/// each category's existing embeddings. `general` is never suggested since
+/// it is the fallback; `None` means nothing scored at least `threshold`.
+pub fn suggest_category(
+    semantic_search: &SemanticSearch,
+    all_notes: &[Note],
+    embedding: &[f32],
+    threshold: f32,
+) -> Option<Suggestion> {
+    let embeddings = semantic_search.embeddings_by_text();
+    let mut sums: HashMap<&str, (Vec<f32>, usize)> = HashMap::new();
+    for note in all_notes {
+        if note.category.is_empty() || note.category == "general" {
+            continue;
+        }
+        let Some(vector) = embeddings.get(note.text.as_str()).copied() else {
+            continue;
+        };
+        let (sum, count) = sums
+            .entry(note.category.as_str())
+            .or_insert_with(|| (vec![0.0; vector.len()], 0));
+        for (total, value) in sum.iter_mut().zip(vector) {
+            *total += value;
+        }
+        *count += 1;
+    }
+
+    sums.into_iter()
+        .filter(|(_, (_, count))| *count >= MIN_EXAMPLES)
+        .map(|(category, (sum, _))| Suggestion {
+            category: category.to_string(),
+            confidence: cosine_similarity(&sum, embedding),
+        })
+        .filter(|suggestion| suggestion.confidence >= threshold)
+        .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
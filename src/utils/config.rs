@@ -0,0 +1,175 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use crate::utils::embedder::{EmbeddingBackend, EmbeddingModel};
+use crate::utils::quantize::Quantization;
+
+const CONFIG_FILE: &str = "config.json";
+
+/// User settings read from `~/notes/config.json`. Every field is optional in
+/// the file; missing ones take their defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub dedupe: DedupeConfig,
+    pub categorize: CategorizeConfig,
+    pub models: ModelsConfig,
+    pub autotag: AutotagConfig,
+    pub entities: EntitiesConfig,
+    pub index: IndexConfig,
+    pub embedding: EmbeddingConfig,
+    pub cache: CacheConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DedupeConfig {
+    /// Check new notes against existing ones before adding them
+    pub enabled: bool,
+    /// Cosine similarity at or above which two notes count as duplicates
+    pub threshold: f32,
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        Self { enabled: true, threshold: 0.92 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CategorizeConfig {
+    /// Minimum similarity to a category centroid for a suggestion
+    pub threshold: f32,
+}
+
+impl Default for CategorizeConfig {
+    fn default() -> Self {
+        Self { threshold: 0.45 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutotagConfig {
+    /// Extract auto-tags whenever a note is added
+    pub on_add: bool,
+    /// Maximum number of auto-tags per note
+    pub count: usize,
+    /// Minimum keyword score for an auto-tag
+    pub min_score: f32,
+}
+
+impl Default for AutotagConfig {
+    fn default() -> Self {
+        Self { on_add: false, count: 3, min_score: 0.3 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EntitiesConfig {
+    /// Extract named entities whenever a note is added
+    pub on_add: bool,
+    /// Minimum NER confidence for an entity to be recorded
+    pub min_score: f64,
+}
+
+impl Default for EntitiesConfig {
+    fn default() -> Self {
+        Self { on_add: false, min_score: 0.8 }
+    }
+}
+
+/// HNSW parameters for the semantic search index. Changing `m` or
+/// `ef_construction` rebuilds the index on the next run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexConfig {
+    /// Maximum connections per node; higher improves recall but uses more memory
+    pub m: usize,
+    /// Candidate list size while building the graph
+    pub ef_construction: usize,
+    /// Candidate list size while searching; higher improves recall but is slower
+    pub ef_search: usize,
+    /// Search compressed vectors instead of the HNSW graph
+    pub quantization: Quantization,
+    /// Candidates per wanted result rescored at full precision when quantized
+    pub rescore_factor: usize,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self { m: 16, ef_construction: 200, ef_search: 64, quantization: Quantization::None, rescore_factor: 4 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    /// Which embedder turns notes into vectors
+    pub backend: EmbeddingBackend,
+    /// Model for the rust-bert backend; changing it re-embeds every note
+    pub model: EmbeddingModel,
+    /// Vector length for the hashing backend
+    pub hashing_dimension: usize,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self { backend: EmbeddingBackend::RustBert, model: EmbeddingModel::MinilmL6, hashing_dimension: 384 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Reuse the embedding of text that was encoded before
+    pub enabled: bool,
+    /// Embeddings kept before the oldest are evicted
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { enabled: true, max_entries: 5000 }
+    }
+}
+
+/// Local model directories; unset ones default to `~/notes/models/<name>`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelsConfig {
+    pub embeddings: Option<PathBuf>,
+    pub summarization: Option<PathBuf>,
+    pub question_answering: Option<PathBuf>,
+    pub ner: Option<PathBuf>,
+    pub onnx: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        let mut path = home_dir().expect("Could not find home directory");
+        path.push("notes");
+        fs::create_dir_all(&path).expect("Failed to create notes directory");
+        path.push(CONFIG_FILE);
+        path
+    }
+
+    /// Load the config, falling back to defaults when the file doesn't exist.
+    pub fn load() -> io::Result<Config> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid config file {}: {}", path.display(), e),
+            )
+        })
+    }
+}
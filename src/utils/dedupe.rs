@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, IsTerminal, Write};
+use crate::utils::file_writing::write_to_file;
+use crate::utils::notes::{self, Note};
+use crate::utils::semantic_search::{SearchError, SemanticSearch};
+
+/// What to do with a new note that duplicates an existing one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateAction {
+    Skip,
+    Merge,
+    Bump,
+    Add,
+}
+
+/// Ask on the terminal how to handle a near-duplicate. When stdin isn't a
+/// terminal (e.g. it held the note text) there is no one to ask, so this
+/// fails rather than guessing; `--force` adds the note without asking.
+pub fn prompt_duplicate_action(existing: &Note, score: f32) -> io::Result<DuplicateAction> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::other(format!(
+            "Note looks like note #{} (similarity {:.2}) and stdin isn't a terminal to ask what to do; pass --force to add it anyway",
+            existing.id, score
+        )));
+    }
+    println!(
+        "This looks like note #{} (similarity {:.2}):\n  [{}] [{}] {}",
+        existing.id, score, existing.date, existing.category, existing.text
+    );
+    loop {
+        print!("[s]kip, [m]erge into it, [b]ump it to today, or [a]dd anyway? [s] ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            println!();
+            return Ok(DuplicateAction::Skip);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "" | "s" | "skip" => return Ok(DuplicateAction::Skip),
+            "m" | "merge" => return Ok(DuplicateAction::Merge),
+            "b" | "bump" => return Ok(DuplicateAction::Bump),
+            "a" | "add" => return Ok(DuplicateAction::Add),
+            _ => println!("Please answer s, m, b or a."),
+        }
+    }
+}
+
+/// Combine two note bodies, keeping just one when it already contains the
+/// other.
+pub fn merge_texts(existing: &str, new: &str) -> String {
+    let (existing_lower, new_lower) = (existing.to_lowercase(), new.to_lowercase());
+    if existing_lower.contains(&new_lower) {
+        existing.to_string()
+    } else if new_lower.contains(&existing_lower) {
+        new.to_string()
+    } else {
+        format!("{} | {}", existing, new)
+    }
+}
+
+/// Add a note with its already computed embedding, first checking it
+/// against existing notes when `threshold` is given. When a duplicate was
+/// found, returns a short description of how it was handled.
+pub fn add_with_dedupe(
+    semantic_search: &mut SemanticSearch,
+    category: &str,
+    text: &str,
+    embedding: Vec<f32>,
+    threshold: Option<f32>,
+) -> Result<Option<String>, SearchError> {
+    let duplicate = match threshold {
+        Some(threshold) => semantic_search.find_duplicate(&embedding, threshold)?,
+        None => None,
+    };
+    let Some(duplicate) = duplicate else {
+        write_to_file(Some(category), text)?;
+        semantic_search.add_embedding(text, embedding)?;
+        return Ok(None);
+    };
+
+    let existing = duplicate.note;
+    let outcome = match prompt_duplicate_action(&existing, duplicate.score)? {
+        DuplicateAction::Skip => format!("Skipped, kept note #{}", existing.id),
+        DuplicateAction::Add => {
+            write_to_file(Some(category), text)?;
+            semantic_search.add_embedding(text, embedding)?;
+            "Added as a new note".to_string()
+        }
+        DuplicateAction::Bump => {
+            notes::bump_note(existing.id)?;
+            format!("Bumped note #{} to today", existing.id)
+        }
+        DuplicateAction::Merge => {
+            let merged = merge_texts(&existing.text, text);
+            notes::replace_note_text(existing.id, &merged)?;
+            replace_embedding(semantic_search, &existing.text, &merged)?;
+            format!("Merged into note #{}", existing.id)
+        }
+    };
+    Ok(Some(outcome))
+}
+
+/// Swap the embedding for `old_text` with one for `new_text`, keeping the old
+/// one if another note still has that text.
+pub fn replace_embedding(semantic_search: &mut SemanticSearch, old_text: &str, new_text: &str) -> Result<(), SearchError> {
+    if old_text == new_text {
+        return Ok(());
+    }
+    if !notes::load_notes()?.iter().any(|note| note.text == old_text) {
+        semantic_search.remove_note_text(old_text)?;
+    }
+    semantic_search.add_note(new_text)
+}
+
+// Neighbours examined per note when clustering
+const CLUSTER_NEIGHBOURS: usize = 10;
+
+/// Lowercase, drop punctuation and collapse whitespace, so trivially
+/// different copies of a note compare equal.
+pub fn normalize_text(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Group notes that have equal normalized text or embeddings at least
+/// `threshold` similar. Only groups of two or more are returned, each in
+/// file order.
+pub fn find_clusters(semantic_search: &SemanticSearch, all_notes: &[Note], threshold: f32) -> Vec<Vec<Note>> {
+    // Union-find over note positions
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+    fn union(parent: &mut [usize], a: usize, b: usize) {
+        let (ra, rb) = (find(parent, a), find(parent, b));
+        if ra != rb {
+            parent[ra.max(rb)] = ra.min(rb);
+        }
+    }
+    let mut parent: Vec<usize> = (0..all_notes.len()).collect();
+
+    let mut by_text: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, note) in all_notes.iter().enumerate() {
+        by_text.entry(note.text.clone()).or_default().push(i);
+    }
+
+    let mut by_normalized: HashMap<String, usize> = HashMap::new();
+    for (i, note) in all_notes.iter().enumerate() {
+        // Punctuation- or emoji-only notes all normalize to nothing
+        let normalized = normalize_text(&note.text);
+        if normalized.is_empty() {
+            continue;
+        }
+        let first = *by_normalized.entry(normalized).or_insert(i);
+        union(&mut parent, first, i);
+    }
+
+    let embeddings = semantic_search.embeddings_by_text();
+    for (text, positions) in &by_text {
+        let Some(embedding) = embeddings.get(text.as_str()) else {
+            continue;
+        };
+        // One extra, since the note finds itself
+        for (neighbour, score) in semantic_search.similar_to(embedding, CLUSTER_NEIGHBOURS + 1) {
+            if score < threshold {
+                continue;
+            }
+            if let Some(others) = by_text.get(&neighbour) {
+                union(&mut parent, positions[0], others[0]);
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<Note>> = HashMap::new();
+    for (i, note) in all_notes.iter().enumerate() {
+        let root = find(&mut parent, i);
+        clusters.entry(root).or_default().push(note.clone());
+    }
+    let mut clusters: Vec<Vec<Note>> = clusters.into_values().filter(|c| c.len() > 1).collect();
+    clusters.sort_by_key(|cluster| cluster[0].id);
+    clusters
+}
+
+/// Edits collected while reviewing clusters, applied together at the end so
+/// note IDs stay valid during the review.
+#[derive(Default)]
+pub struct DedupePlan {
+    pub delete: HashSet<usize>,
+    pub replace: HashMap<usize, String>,
+}
+
+/// Ask how to resolve one cluster and record the answer in `plan`. Returns
+/// `false` when the user wants to stop reviewing.
+pub fn review_cluster(cluster: &[Note], plan: &mut DedupePlan) -> io::Result<bool> {
+    for note in cluster {
+        println!("{:>4}  {}", note.id, note.to_line());
+    }
+    loop {
+        print!("Keep which note? [ID, m=merge into newest, s=skip, q=quit] [s] ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            println!();
+            return Ok(false);
+        }
+        let answer = answer.trim().to_lowercase();
+        match answer.as_str() {
+            "" | "s" | "skip" => return Ok(true),
+            "q" | "quit" => return Ok(false),
+            "m" | "merge" => {
+                let newest = cluster.last().expect("clusters are never empty");
+                let merged = cluster
+                    .iter()
+                    .map(|note| note.text.as_str())
+                    .fold(String::new(), |acc, text| {
+                        if acc.is_empty() { text.to_string() } else { merge_texts(&acc, text) }
+                    });
+                plan.replace.insert(newest.id, merged);
+                plan.delete.extend(cluster.iter().filter(|n| n.id != newest.id).map(|n| n.id));
+                return Ok(true);
+            }
+            _ => match answer.parse::<usize>() {
+                Ok(id) if cluster.iter().any(|note| note.id == id) => {
+                    plan.delete.extend(cluster.iter().filter(|n| n.id != id).map(|n| n.id));
+                    return Ok(true);
+                }
+                _ => println!("Please enter one of the IDs above, m, s or q."),
+            },
+        }
+    }
+}
+
+/// Apply a plan to `notes.txt`, then bring the embeddings in line: vectors
+/// for texts that no longer exist are dropped and merged texts are embedded.
+pub fn apply_plan(semantic_search: &mut SemanticSearch, plan: &DedupePlan) -> Result<usize, SearchError> {
+    let before = notes::load_notes()?;
+    let mut after = before.clone();
+    for note in after.iter_mut() {
+        if let Some(text) = plan.replace.get(&note.id) {
+            note.text = text.clone();
+        }
+    }
+    after.retain(|note| !plan.delete.contains(&note.id));
+    notes::save_notes(&after)?;
+
+    let remaining: HashSet<&str> = after.iter().map(|note| note.text.as_str()).collect();
+    let orphaned: Vec<String> = before
+        .iter()
+        .map(|note| note.text.clone())
+        .filter(|text| !remaining.contains(text.as_str()))
+        .collect();
+    semantic_search.remove_notes(&orphaned)?;
+    for text in plan.replace.values() {
+        if !before.iter().any(|note| &note.text == text) {
+            semantic_search.add_note(text)?;
+        }
+    }
+    Ok(before.len() - after.len())
+}
@@ -0,0 +1,79 @@
+use chrono::{Local, NaiveDate};
+use clap::ValueEnum;
+use rust_bert::pipelines::summarization::SummarizationModel;
+use std::collections::BTreeMap;
+use crate::utils::models::ModelError;
+use crate::utils::notes::Note;
+use crate::utils::semantic_search::SemanticSearch;
+use crate::utils::topics::find_topics;
+
+// BART reads at most 1024 tokens; stay well under that in characters
+const MAX_INPUT_CHARS: usize = 3000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    /// One section per category
+    #[default]
+    Category,
+    /// One section per topic (needs the embedding model)
+    Topic,
+}
+
+/// Split notes into titled sections. Grouping by topic uses the stored
+/// embeddings, so `semantic_search` is only needed for `GroupBy::Topic`.
+pub fn group_notes(notes: &[Note], group_by: GroupBy, semantic_search: Option<&SemanticSearch>) -> Vec<(String, Vec<Note>)> {
+    match (group_by, semantic_search) {
+        (GroupBy::Topic, Some(semantic_search)) => find_topics(semantic_search, notes, None)
+            .into_iter()
+            .map(|topic| (topic.label, topic.notes))
+            .collect(),
+        _ => {
+            let mut groups: BTreeMap<String, Vec<Note>> = BTreeMap::new();
+            for note in notes {
+                groups.entry(note.category.clone()).or_default().push(note.clone());
+            }
+            groups.into_iter().collect()
+        }
+    }
+}
+
+/// Render a markdown digest with a generated summary for each section,
+/// followed by the notes it covers.
+pub fn build_digest(
+    model: &SummarizationModel,
+    sections: &[(String, Vec<Note>)],
+    since: NaiveDate,
+) -> Result<String, ModelError> {
+    let today = Local::now().date_naive();
+    let mut digest = format!("# Digest: {} to {}\n", since, today);
+    if sections.is_empty() {
+        digest.push_str("\nNo notes in this period.\n");
+        return Ok(digest);
+    }
+
+    let inputs: Vec<String> = sections.iter().map(|(_, notes)| summary_input(notes)).collect();
+    let summaries = model.summarize(&inputs)?;
+
+    for ((title, notes), summary) in sections.iter().zip(summaries) {
+        digest.push_str(&format!("\n## {} ({} notes)\n\n{}\n\n", title, notes.len(), summary.trim()));
+        for note in notes {
+            digest.push_str(&format!("- [{}] {} (#{})\n", note.date, note.text, note.id));
+        }
+    }
+    Ok(digest)
+}
+
+/// Join note texts into one passage for the summarizer, cut at a sentence
+/// boundary once it gets too long for the model.
+fn summary_input(notes: &[Note]) -> String {
+    let mut input = String::new();
+    for note in notes {
+        let sentence = note.text.trim().trim_end_matches('.');
+        if !input.is_empty() && input.len() + sentence.len() + 2 > MAX_INPUT_CHARS {
+            break;
+        }
+        input.push_str(sentence);
+        input.push_str(". ");
+    }
+    input
+}
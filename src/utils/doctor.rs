@@ -0,0 +1,416 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::utils::config::Config;
+use crate::utils::embedder::{configured_model, EmbeddingBackend};
+use crate::utils::models::{require_files, ModelKind};
+use crate::utils::notes::{self, Note};
+use crate::utils::semantic_search::{SearchError, SemanticSearch};
+use crate::utils::vector_store::{StoredEmbedding, VectorStore};
+
+// How far an embedding's length may stray from 1
+const NORM_TOLERANCE: f32 = 1e-3;
+// How many line numbers or ids to list in a finding
+const MAX_LISTED: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Ok,
+    Warning,
+    Problem,
+}
+
+/// Something `--repair` knows how to fix.
+#[derive(PartialEq)]
+enum Repair {
+    /// Embed notes missing an embedding, drop orphans, and re-embed if
+    /// the model changed (loading `SemanticSearch` does that)
+    Reindex,
+    /// Rewrite the store with normalized embeddings
+    Normalize,
+    RebuildIndex,
+    /// Give the owner read and write access and take away write access
+    /// from everyone else
+    Permissions(Vec<PathBuf>),
+}
+
+struct Finding {
+    status: Status,
+    message: String,
+    fix: Option<String>,
+    repair: Option<Repair>,
+}
+
+impl Finding {
+    fn ok(message: String) -> Self {
+        Self { status: Status::Ok, message, fix: None, repair: None }
+    }
+
+    fn warning(message: String, fix: String) -> Self {
+        Self { status: Status::Warning, message, fix: Some(fix), repair: None }
+    }
+
+    fn problem(message: String, fix: String, repair: Option<Repair>) -> Self {
+        Self { status: Status::Problem, message, fix: Some(fix), repair }
+    }
+
+    fn print(&self) {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warn",
+            Status::Problem => "FAIL",
+        };
+        println!("{:<5} {}", label, self.message);
+        if let Some(fix) = &self.fix {
+            println!("      fix: {}", fix);
+        }
+    }
+}
+
+/// Check the notes, embeddings, index, model and file permissions, printing
+/// each finding with how to fix it. With `repair`, the fixable problems are
+/// fixed afterwards. Returns the number of problems left.
+pub fn run_doctor(repair: bool) -> Result<usize, SearchError> {
+    let notes_path = notes::get_notes_path();
+    let dir = notes_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut findings = Vec::new();
+    let mut report = |finding: Finding| {
+        finding.print();
+        findings.push(finding);
+    };
+
+    let config = match Config::load() {
+        Ok(config) => {
+            report(Finding::ok("config.json is valid".to_string()));
+            config
+        }
+        Err(e) => {
+            report(Finding::problem(e.to_string(), "fix the file or delete it to use the defaults".to_string(), None));
+            Config::default()
+        }
+    };
+
+    let notes = match notes::load_notes() {
+        Ok(notes) => {
+            report(check_note_format(&notes, &notes_path));
+            notes
+        }
+        Err(e) => {
+            report(Finding::problem(
+                format!("{} can't be read: {}", notes_path.display(), e),
+                "check the file is readable and valid UTF-8".to_string(),
+                None,
+            ));
+            Vec::new()
+        }
+    };
+
+    let (model_id, dimension) = configured_model(&config);
+    let store_ok = match VectorStore::open(&dir, &model_id) {
+        Ok((store, embeddings)) => {
+            report(check_coverage(&notes, &embeddings));
+            report(check_normalized(&embeddings));
+            let model_matches = store.model_id() == model_id
+                && dimension.zip(store.dimension()).is_none_or(|(expected, stored)| expected == stored);
+            report(check_model_match(&store, &model_id, dimension, model_matches));
+            model_matches
+        }
+        Err(e) => {
+            report(Finding::problem(
+                format!("The embedding store can't be read: {}", e),
+                format!(
+                    "delete {} and {}, then run `pad reindex`",
+                    dir.join("embeddings.bin").display(),
+                    dir.join("embeddings.jsonl").display()
+                ),
+                None,
+            ));
+            false
+        }
+    };
+
+    let model = check_model(&config);
+    let model_ok = model.status != Status::Problem;
+    // A model that would have to be downloaded first isn't loaded, since
+    // that fails on machines without network access
+    let model_local = model.status == Status::Ok;
+    report(model);
+
+    // Loading the index with a mismatched store would start re-embedding,
+    // so that waits for an explicit repair
+    if !model_local {
+        report(Finding::warning(
+            "Search index not checked: the embedding model isn't available locally".to_string(),
+            "install it with `pad model install --from <dir>`, then run `pad doctor` again".to_string(),
+        ));
+    } else if store_ok {
+        match SemanticSearch::new() {
+            Ok(semantic_search) => report(check_index(&semantic_search)),
+            Err(e) => report(Finding::problem(
+                format!("The search index can't be loaded: {}", e),
+                "check the model files, or delete the index.* files so the index is rebuilt".to_string(),
+                None,
+            )),
+        }
+    }
+
+    report(check_permissions(&dir));
+
+    let problems = findings.iter().filter(|f| f.status == Status::Problem).count();
+    let repairable: Vec<Repair> = findings.into_iter().filter_map(|f| f.repair).collect();
+    if problems == 0 {
+        println!("\nNo problems found");
+        return Ok(0);
+    }
+    if !repair {
+        println!("\n{} problem(s) found", problems);
+        if !repairable.is_empty() {
+            println!("Run `pad doctor --repair` to fix {} of them", repairable.len());
+        }
+        return Ok(problems);
+    }
+
+    println!();
+    let fixed = apply_repairs(repairable, &notes, model_ok)?;
+    Ok(problems - fixed)
+}
+
+fn apply_repairs(repairs: Vec<Repair>, notes: &[Note], model_ok: bool) -> Result<usize, SearchError> {
+    let mut fixed = 0;
+    let needs_search = repairs.iter().any(|r| !matches!(r, Repair::Permissions(_)));
+    let mut semantic_search = if needs_search && model_ok {
+        match SemanticSearch::new() {
+            Ok(semantic_search) => Some(semantic_search),
+            Err(e) => {
+                println!("Can't load the search index: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    for repair in [Repair::Normalize, Repair::Reindex, Repair::RebuildIndex] {
+        // Several findings can share one repair
+        let findings = repairs.iter().filter(|r| **r == repair).count();
+        if findings == 0 {
+            continue;
+        }
+        let Some(semantic_search) = semantic_search.as_mut() else {
+            println!("Skipped {}: the search index isn't available", repair_label(&repair));
+            continue;
+        };
+        match repair {
+            Repair::Normalize => semantic_search.compact_store()?,
+            Repair::Reindex => {
+                let report = semantic_search.reindex(notes, false)?;
+                println!("Embedded {} note(s), dropped {} orphaned embedding(s)", report.embedded, report.removed);
+            }
+            _ => semantic_search.rebuild_index()?,
+        }
+        println!("Repaired: {}", repair_label(&repair));
+        fixed += findings;
+    }
+
+    for repair in repairs {
+        if let Repair::Permissions(paths) = repair {
+            for path in &paths {
+                fix_permissions(path)?;
+            }
+            println!("Repaired: permissions of {} file(s)", paths.len());
+            fixed += 1;
+        }
+    }
+    Ok(fixed)
+}
+
+fn repair_label(repair: &Repair) -> &'static str {
+    match repair {
+        Repair::Reindex => "embeddings reconciled with the notes",
+        Repair::Normalize => "embeddings normalized",
+        Repair::RebuildIndex => "search index rebuilt",
+        Repair::Permissions(_) => "file permissions",
+    }
+}
+
+/// Lines without the `[date] [category]` prefix still load, but have no
+/// date or category to filter on.
+fn check_note_format(notes: &[Note], path: &Path) -> Finding {
+    let unformatted: Vec<usize> = notes
+        .iter()
+        .filter(|note| note.parsed_date().is_none())
+        .map(|note| note.id)
+        .collect();
+    if unformatted.is_empty() {
+        return Finding::ok(format!("notes.txt parses cleanly ({} notes)", notes.len()));
+    }
+    Finding::warning(
+        format!(
+            "{} note(s) lack a valid [date] [category] prefix: {}",
+            unformatted.len(),
+            list(&unformatted)
+        ),
+        format!("edit {} to add one, e.g. [2024-01-31] [general] <text>", path.display()),
+    )
+}
+
+fn check_coverage(notes: &[Note], embeddings: &[StoredEmbedding]) -> Finding {
+    let embedded: HashSet<&str> = embeddings.iter().map(|e| e.text.as_str()).collect();
+    let texts: HashSet<&str> = notes.iter().map(|note| note.text.as_str()).collect();
+    let missing: Vec<usize> = notes
+        .iter()
+        .filter(|note| !embedded.contains(note.text.as_str()))
+        .map(|note| note.id)
+        .collect();
+    let orphans = embeddings.iter().filter(|e| !texts.contains(e.text.as_str())).count();
+    if missing.is_empty() && orphans == 0 {
+        return Finding::ok(format!("Every note has an embedding ({} stored)", embeddings.len()));
+    }
+
+    let mut issues = Vec::new();
+    if !missing.is_empty() {
+        issues.push(format!("{} note(s) have no embedding: {}", missing.len(), list(&missing)));
+    }
+    if orphans > 0 {
+        issues.push(format!("{} embedding(s) belong to no note", orphans));
+    }
+    Finding::problem(issues.join("; "), "run `pad reindex`".to_string(), Some(Repair::Reindex))
+}
+
+fn check_normalized(embeddings: &[StoredEmbedding]) -> Finding {
+    let off = embeddings
+        .iter()
+        .filter(|e| (e.embedding.iter().map(|v| v * v).sum::<f32>().sqrt() - 1.0).abs() > NORM_TOLERANCE)
+        .count();
+    if off == 0 {
+        return Finding::ok("Embeddings are normalized".to_string());
+    }
+    // Vectors are normalized again on load, so search still works
+    Finding::problem(
+        format!("{} stored embedding(s) aren't unit length", off),
+        "run `pad doctor --repair` to rewrite them normalized".to_string(),
+        Some(Repair::Normalize),
+    )
+}
+
+fn check_model_match(store: &VectorStore, model_id: &str, dimension: Option<usize>, matches: bool) -> Finding {
+    let stored_dimension = store.dimension().map_or("no".to_string(), |d| d.to_string());
+    if matches {
+        return Finding::ok(format!("Embeddings match {} ({} dimensions)", model_id, stored_dimension));
+    }
+    let expected = dimension.map_or(String::new(), |d| format!(", {} dimensions", d));
+    Finding::problem(
+        format!(
+            "Embeddings were made with {} ({} dimensions) but {}{} is configured",
+            store.model_id(),
+            stored_dimension,
+            model_id,
+            expected
+        ),
+        "run `pad reindex` to re-embed every note with the configured model".to_string(),
+        Some(Repair::Reindex),
+    )
+}
+
+fn check_model(config: &Config) -> Finding {
+    match config.embedding.backend {
+        EmbeddingBackend::Hashing => Finding::ok("The hashing embedder needs no model".to_string()),
+        EmbeddingBackend::Onnx if !cfg!(feature = "onnx") => Finding::problem(
+            "The onnx backend is configured but pad was built without ONNX support".to_string(),
+            "rebuild with `cargo install --path . --features onnx`, or change `embedding.backend`".to_string(),
+            None,
+        ),
+        EmbeddingBackend::Onnx => model_files(ModelKind::Onnx, config),
+        EmbeddingBackend::RustBert => {
+            let dir = ModelKind::Embeddings.dir(config);
+            if config.models.embeddings.is_none() && !dir.exists() {
+                return Finding::warning(
+                    format!("No local copy of {}; it is downloaded on first use", config.embedding.model.id()),
+                    "on machines without network access, run `pad model install --from <dir>`".to_string(),
+                );
+            }
+            model_files(ModelKind::Embeddings, config)
+        }
+    }
+}
+
+fn model_files(kind: ModelKind, config: &Config) -> Finding {
+    let dir = kind.dir(config);
+    match require_files(kind, &dir) {
+        Ok(()) => Finding::ok(format!("Model files present in {}", dir.display())),
+        Err(e) => Finding::problem(
+            e.to_string().lines().next().unwrap_or_default().to_string(),
+            format!("run `pad model install {} --from <dir>`", kind.key()),
+            None,
+        ),
+    }
+}
+
+fn check_index(semantic_search: &SemanticSearch) -> Finding {
+    let misses = semantic_search.self_check();
+    if misses.is_empty() {
+        return Finding::ok("The search index finds every note from its own embedding".to_string());
+    }
+    Finding::problem(
+        format!("The search index doesn't return {} embedding(s) as their own nearest neighbour", misses.len()),
+        "run `pad doctor --repair` to rebuild it, and raise `index.ef_search` if this comes back".to_string(),
+        Some(Repair::RebuildIndex),
+    )
+}
+
+/// Files in the notes directory must be readable and writable by their
+/// owner, and shouldn't be writable by anyone else.
+fn check_permissions(dir: &Path) -> Finding {
+    let mut paths = vec![dir.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(dir) {
+        paths.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()));
+    }
+    let bad: Vec<PathBuf> = paths.into_iter().filter(|path| !permissions_ok(path)).collect();
+    if bad.is_empty() {
+        return Finding::ok("File permissions are fine".to_string());
+    }
+    let names: Vec<String> = bad.iter().take(MAX_LISTED).map(|p| p.display().to_string()).collect();
+    Finding::problem(
+        format!("{} path(s) have unsafe or too strict permissions: {}", bad.len(), names.join(", ")),
+        "make them readable and writable by you and writable by no one else, e.g. `chmod 600` (`chmod 700` for the directory)".to_string(),
+        Some(Repair::Permissions(bad)),
+    )
+}
+
+#[cfg(unix)]
+fn permissions_ok(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(metadata) = fs::metadata(path) else { return false };
+    let mode = metadata.permissions().mode();
+    let owner = if metadata.is_dir() { 0o700 } else { 0o600 };
+    mode & owner == owner && mode & 0o022 == 0
+}
+
+#[cfg(not(unix))]
+fn permissions_ok(path: &Path) -> bool {
+    fs::metadata(path).map_or(false, |metadata| !metadata.permissions().readonly())
+}
+
+#[cfg(unix)]
+fn fix_permissions(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = fs::metadata(path)?;
+    let owner = if metadata.is_dir() { 0o700 } else { 0o600 };
+    let mode = (metadata.permissions().mode() | owner) & !0o022;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn fix_permissions(path: &Path) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions)
+}
+
+fn list(ids: &[usize]) -> String {
+    let mut shown: Vec<String> = ids.iter().take(MAX_LISTED).map(|id| format!("#{}", id)).collect();
+    if ids.len() > MAX_LISTED {
+        shown.push(format!("and {} more", ids.len() - MAX_LISTED));
+    }
+    shown.join(", ")
+}
@@ -0,0 +1,315 @@
+use rust_bert::pipelines::sentence_embeddings::{SentenceEmbeddingsModel, SentenceEmbeddingsModelType};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::utils::config::Config;
+use crate::utils::lexical::tokenize;
+use crate::utils::models::{load_embedding_model, ModelError, ModelKind};
+
+/// Turns text into vectors for semantic search.
+pub trait Embedder {
+    /// Encode a batch of texts into one vector each. Vectors needn't be
+    /// normalized; `SemanticSearch` does that.
+    fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ModelError>;
+
+    /// Length of every vector `encode` returns.
+    fn dimension(&self) -> usize;
+
+    /// Identifies the model, so vectors from different models are never
+    /// compared.
+    fn model_id(&self) -> &str;
+}
+
+/// Which implementation of `Embedder` to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingBackend {
+    /// rust-bert on libtorch
+    #[default]
+    RustBert,
+    /// An ONNX export of the model run with ONNX Runtime (needs the `onnx` feature)
+    Onnx,
+    /// Feature hashing of words and character trigrams; no model needed, but
+    /// only matches shared vocabulary
+    Hashing,
+}
+
+/// Sentence embedding models the rust-bert backend can use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmbeddingModel {
+    /// all-MiniLM-L6-v2: fast, English, 384 dimensions
+    #[default]
+    MinilmL6,
+    /// all-MiniLM-L12-v2: slower and a little more accurate, 384 dimensions
+    MinilmL12,
+    /// distiluse-base-multilingual-cased: 15 languages, 512 dimensions
+    Multilingual,
+}
+
+impl EmbeddingModel {
+    /// Name recorded in the embedding store.
+    pub fn id(&self) -> &'static str {
+        match self {
+            EmbeddingModel::MinilmL6 => "all-MiniLM-L6-v2",
+            EmbeddingModel::MinilmL12 => "all-MiniLM-L12-v2",
+            EmbeddingModel::Multilingual => "distiluse-base-multilingual-cased",
+        }
+    }
+
+    pub fn dimension(&self) -> usize {
+        match self {
+            EmbeddingModel::MinilmL6 | EmbeddingModel::MinilmL12 => 384,
+            EmbeddingModel::Multilingual => 512,
+        }
+    }
+
+    pub fn model_type(&self) -> SentenceEmbeddingsModelType {
+        match self {
+            EmbeddingModel::MinilmL6 => SentenceEmbeddingsModelType::AllMiniLmL6V2,
+            EmbeddingModel::MinilmL12 => SentenceEmbeddingsModelType::AllMiniLmL12V2,
+            EmbeddingModel::Multilingual => SentenceEmbeddingsModelType::DistiluseBaseMultilingualCased,
+        }
+    }
+}
+
+/// Load the embedder selected in the config.
+pub fn load_embedder(config: &Config) -> Result<Box<dyn Embedder>, ModelError> {
+    match config.embedding.backend {
+        EmbeddingBackend::RustBert => Ok(Box::new(RustBertEmbedder::new(config)?)),
+        EmbeddingBackend::Onnx => load_onnx_embedder(config),
+        EmbeddingBackend::Hashing => Ok(Box::new(HashingEmbedder::new(config.embedding.hashing_dimension))),
+    }
+}
+
+/// Model id and vector dimension the configured embedder will have, worked
+/// out without loading it. The dimension of an ONNX model isn't known until
+/// it runs.
+pub fn configured_model(config: &Config) -> (String, Option<usize>) {
+    match config.embedding.backend {
+        EmbeddingBackend::RustBert => {
+            let model = config.embedding.model;
+            (model.id().to_string(), Some(model.dimension()))
+        }
+        EmbeddingBackend::Onnx => (onnx_model_id(&ModelKind::Onnx.dir(config)), None),
+        EmbeddingBackend::Hashing => {
+            let embedder = HashingEmbedder::new(config.embedding.hashing_dimension);
+            (embedder.model_id.clone(), Some(embedder.dimension))
+        }
+    }
+}
+
+// Bytes hashed from each end of model.onnx to fingerprint it
+const ONNX_FINGERPRINT_BYTES: u64 = 1 << 20;
+
+/// Id of the ONNX model in `dir`: the directory name and a fingerprint of
+/// `model.onnx`, so dropping another export into the same directory
+/// re-embeds the notes. Hashing gigabytes of weights on every start would
+/// be slow, so the fingerprint covers the file size and its first and
+/// last megabyte, where the graph and the weights of both ends sit.
+fn onnx_model_id(dir: &Path) -> String {
+    let name = dir.file_name().map_or_else(|| "model".into(), |name| name.to_string_lossy());
+    match onnx_fingerprint(&dir.join("model.onnx")) {
+        Ok(fingerprint) => format!("onnx:{}-{:016x}", name, fingerprint),
+        // Loading reports the missing file
+        Err(_) => format!("onnx:{}", name),
+    }
+}
+
+fn onnx_fingerprint(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut bytes = len.to_le_bytes().to_vec();
+    (&mut file).take(ONNX_FINGERPRINT_BYTES).read_to_end(&mut bytes)?;
+    if len > ONNX_FINGERPRINT_BYTES {
+        file.seek(SeekFrom::Start(len.saturating_sub(ONNX_FINGERPRINT_BYTES).max(ONNX_FINGERPRINT_BYTES)))?;
+        file.read_to_end(&mut bytes)?;
+    }
+    Ok(fnv1a(&bytes))
+}
+
+#[cfg(feature = "onnx")]
+pub fn load_onnx_embedder(config: &Config) -> Result<Box<dyn Embedder>, ModelError> {
+    Ok(Box::new(onnx::OnnxEmbedder::new(config)?))
+}
+
+#[cfg(not(feature = "onnx"))]
+pub fn load_onnx_embedder(_config: &Config) -> Result<Box<dyn Embedder>, ModelError> {
+    Err(ModelError::Unsupported("the onnx embedding backend; rebuild pad with `--features onnx`"))
+}
+
+pub struct RustBertEmbedder {
+    model: SentenceEmbeddingsModel,
+    model_id: &'static str,
+    dimension: usize,
+}
+
+impl RustBertEmbedder {
+    pub fn new(config: &Config) -> Result<Self, ModelError> {
+        let model = load_embedding_model(config)?;
+        // Known from the model choice, so no encode is needed to find it
+        let dimension = config.embedding.model.dimension();
+        Ok(Self { model, model_id: config.embedding.model.id(), dimension })
+    }
+}
+
+impl Embedder for RustBertEmbedder {
+    fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ModelError> {
+        Ok(self.model.encode(texts)?)
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn model_id(&self) -> &str {
+        self.model_id
+    }
+}
+
+/// Hashes each word and each character trigram of it into one of
+/// `dimension` buckets, with a hash-chosen sign so collisions tend to
+/// cancel out. Deterministic and instant, which makes it useful where no
+/// model can be installed.
+pub struct HashingEmbedder {
+    dimension: usize,
+    model_id: String,
+}
+
+// Trigrams catch shared word stems but say less than a whole word
+const TRIGRAM_WEIGHT: f32 = 0.5;
+
+impl HashingEmbedder {
+    pub fn new(dimension: usize) -> Self {
+        let dimension = dimension.max(1);
+        Self { dimension, model_id: format!("hashing-{}", dimension) }
+    }
+
+    fn add_feature(&self, vector: &mut [f32], feature: &str, weight: f32) {
+        let hash = fnv1a(feature.as_bytes());
+        let bucket = (hash % self.dimension as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[bucket] += sign * weight;
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ModelError> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut vector = vec![0.0; self.dimension];
+                for token in tokenize(text) {
+                    self.add_feature(&mut vector, &token, 1.0);
+                    let padded: Vec<char> = format!("<{}>", token).chars().collect();
+                    for trigram in padded.windows(3) {
+                        let trigram: String = trigram.iter().collect();
+                        self.add_feature(&mut vector, &format!("#{}", trigram), TRIGRAM_WEIGHT);
+                    }
+                }
+                vector
+            })
+            .collect())
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+}
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(feature = "onnx")]
+mod onnx {
+    use ndarray::{Array2, Axis};
+    use ort::session::Session;
+    use tokenizers::{Encoding, PaddingParams, Tokenizer};
+    use crate::utils::config::Config;
+    use crate::utils::models::{require_files, ModelError, ModelKind};
+    use super::Embedder;
+
+    /// A sentence-transformers model exported to ONNX (`model.onnx`) with
+    /// its Hugging Face `tokenizer.json`. Token embeddings are mean-pooled
+    /// over the attention mask, as the original model does.
+    pub struct OnnxEmbedder {
+        session: Session,
+        tokenizer: Tokenizer,
+        dimension: usize,
+        model_id: String,
+    }
+
+    impl OnnxEmbedder {
+        pub fn new(config: &Config) -> Result<Self, ModelError> {
+            let dir = ModelKind::Onnx.dir(config);
+            require_files(ModelKind::Onnx, &dir)?;
+
+            let session = Session::builder()?.commit_from_file(dir.join("model.onnx"))?;
+            let mut tokenizer = Tokenizer::from_file(dir.join("tokenizer.json"))
+                .map_err(|e| ModelError::Tokenizer(e.to_string()))?;
+            tokenizer.with_padding(Some(PaddingParams::default()));
+            let mut embedder = Self { session, tokenizer, dimension: 0, model_id: super::onnx_model_id(&dir) };
+            embedder.dimension = embedder.encode(&["dimension probe"])?[0].len();
+            Ok(embedder)
+        }
+    }
+
+    impl Embedder for OnnxEmbedder {
+        fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ModelError> {
+            if texts.is_empty() {
+                return Ok(Vec::new());
+            }
+            let encodings = self.tokenizer
+                .encode_batch(texts.to_vec(), true)
+                .map_err(|e| ModelError::Tokenizer(e.to_string()))?;
+            let length = encodings[0].get_ids().len();
+            let shape = (encodings.len(), length);
+            let column = |values: fn(&Encoding) -> &[u32]| {
+                let data = encodings.iter().flat_map(|e| values(e).iter().map(|v| *v as i64)).collect();
+                Array2::from_shape_vec(shape, data).expect("padded encodings have equal length")
+            };
+            let ids = column(Encoding::get_ids);
+            let mask = column(Encoding::get_attention_mask);
+            let types = column(Encoding::get_type_ids);
+
+            let outputs = self.session.run(ort::inputs![
+                "input_ids" => ids,
+                "attention_mask" => mask.clone(),
+                "token_type_ids" => types,
+            ]?)?;
+            let hidden = outputs[0].try_extract_tensor::<f32>()?;
+
+            Ok(hidden
+                .axis_iter(Axis(0))
+                .zip(mask.axis_iter(Axis(0)))
+                .map(|(tokens, mask)| {
+                    let mut pooled = vec![0.0f32; tokens.shape()[1]];
+                    let mut count = 0.0f32;
+                    for (token, keep) in tokens.axis_iter(Axis(0)).zip(mask.iter()) {
+                        if *keep == 1 {
+                            for (total, value) in pooled.iter_mut().zip(token.iter()) {
+                                *total += value;
+                            }
+                            count += 1.0;
+                        }
+                    }
+                    pooled.iter().map(|v| v / count.max(1.0)).collect()
+                })
+                .collect())
+        }
+
+        fn dimension(&self) -> usize {
+            self.dimension
+        }
+
+        fn model_id(&self) -> &str {
+            &self.model_id
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::utils::config::CacheConfig;
+use crate::utils::embedder::{fnv1a, Embedder};
+use crate::utils::models::ModelError;
+
+const CACHE_FILE: &str = "embedding_cache.bin";
+const COUNTERS_FILE: &str = "embedding_cache.json";
+const MAGIC: &[u8; 8] = b"PADCACH1";
+// Model key, text key and dimension before each vector
+const RECORD_HEADER: usize = 8 + 8 + 4;
+// Larger than any embedding model's output; a bigger dimension means the
+// record is corrupt
+const MAX_DIMENSION: usize = 16384;
+
+/// Size and effectiveness of the embedding cache, for `pad index stats`.
+pub struct CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups answered from the cache.
+    pub fn hit_rate(&self) -> f32 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f32 / lookups as f32
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Counters {
+    hits: u64,
+    misses: u64,
+}
+
+struct Record {
+    model: u64,
+    text: u64,
+    vector: Vec<f32>,
+}
+
+/// Vectors already computed, keyed by model and text hash, in the order
+/// they were added. The file is append-only; once it holds a quarter more
+/// than `max_entries`, the oldest entries are dropped and it is rewritten.
+struct Cache {
+    path: PathBuf,
+    counters_path: PathBuf,
+    max_entries: usize,
+    records: Vec<Record>,
+    positions: HashMap<(u64, u64), usize>,
+    counters: Counters,
+    counters_changed: bool,
+}
+
+impl Cache {
+    fn open(dir: &Path, max_entries: usize) -> io::Result<Self> {
+        let counters_path = dir.join(COUNTERS_FILE);
+        let counters = match fs::read_to_string(&counters_path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Counters::default(),
+            Err(e) => return Err(e),
+        };
+        let mut cache = Self {
+            path: dir.join(CACHE_FILE),
+            counters_path,
+            max_entries: max_entries.max(1),
+            records: Vec::new(),
+            positions: HashMap::new(),
+            counters,
+            counters_changed: false,
+        };
+        let (records, len) = cache.read_records()?;
+        cache.records = records;
+        // Appending after a torn or corrupt record would hide everything
+        // written later, so the file is cut back to what was read
+        if fs::metadata(&cache.path).is_ok_and(|meta| meta.len() > len) {
+            OpenOptions::new().write(true).open(&cache.path)?.set_len(len)?;
+        }
+        cache.evict()?;
+        cache.reposition();
+        Ok(cache)
+    }
+
+    /// Every complete record in the file, and the length they take up.
+    /// Records of every model are kept, whatever their dimension. Reading
+    /// stops at a record cut short by a crash or with a corrupt dimension;
+    /// a file with the wrong magic holds nothing.
+    fn read_records(&self) -> io::Result<(Vec<Record>, u64)> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        if reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Ok((Vec::new(), 0));
+        }
+
+        let mut records = Vec::new();
+        let mut len = MAGIC.len() as u64;
+        let mut header = [0u8; RECORD_HEADER];
+        while reader.read_exact(&mut header).is_ok() {
+            let model = u64::from_le_bytes(header[0..8].try_into().unwrap());
+            let text = u64::from_le_bytes(header[8..16].try_into().unwrap());
+            let dimension = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+            if dimension == 0 || dimension > MAX_DIMENSION {
+                break;
+            }
+            let mut bytes = vec![0u8; dimension * 4];
+            if reader.read_exact(&mut bytes).is_err() {
+                break;
+            }
+            let vector = bytes.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+            records.push(Record { model, text, vector });
+            len += (RECORD_HEADER + bytes.len()) as u64;
+        }
+        Ok((records, len))
+    }
+
+    fn get(&self, model: u64, text: u64) -> Option<Vec<f32>> {
+        self.positions.get(&(model, text)).map(|i| self.records[*i].vector.clone())
+    }
+
+    fn insert(&mut self, model: u64, text: u64, vector: Vec<f32>) -> io::Result<()> {
+        if let Some(i) = self.positions.get(&(model, text)) {
+            if self.records[*i].vector.len() == vector.len() {
+                return Ok(());
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let new_file = file.metadata()?.len() == 0;
+        let mut file = BufWriter::new(file);
+        if new_file {
+            file.write_all(MAGIC)?;
+        }
+        write_record(&mut file, model, text, &vector)?;
+        file.flush()?;
+
+        self.positions.insert((model, text), self.records.len());
+        self.records.push(Record { model, text, vector });
+        if self.records.len() > self.max_entries + self.max_entries / 4 {
+            self.evict()?;
+            self.reposition();
+        }
+        Ok(())
+    }
+
+    /// Drop the oldest entries beyond `max_entries` and rewrite the file.
+    fn evict(&mut self) -> io::Result<()> {
+        if self.records.len() <= self.max_entries {
+            return Ok(());
+        }
+        let excess = self.records.len() - self.max_entries;
+        self.records.drain(..excess);
+        self.rewrite()
+    }
+
+    /// Drop every entry of `model`, so it is encoded afresh.
+    fn clear_model(&mut self, model: u64) -> io::Result<()> {
+        let before = self.records.len();
+        self.records.retain(|record| record.model != model);
+        if self.records.len() == before {
+            return Ok(());
+        }
+        self.reposition();
+        self.rewrite()
+    }
+
+    fn rewrite(&self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("bin.tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        file.write_all(MAGIC)?;
+        for record in &self.records {
+            write_record(&mut file, record.model, record.text, &record.vector)?;
+        }
+        file.into_inner()?.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn reposition(&mut self) {
+        self.positions = self
+            .records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| ((record.model, record.text), i))
+            .collect();
+    }
+
+    fn save_counters(&self) -> io::Result<()> {
+        let json = serde_json::to_string(&self.counters).expect("counters serialize");
+        fs::write(&self.counters_path, json)
+    }
+
+    fn count(&mut self, hits: usize, misses: usize) {
+        self.counters.hits += hits as u64;
+        self.counters.misses += misses as u64;
+        self.counters_changed = true;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.records.len(),
+            max_entries: self.max_entries,
+            bytes: fs::metadata(&self.path).map_or(0, |meta| meta.len()),
+            hits: self.counters.hits,
+            misses: self.counters.misses,
+        }
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        // Written once per command rather than on every lookup
+        if self.counters_changed {
+            if let Err(e) = self.save_counters() {
+                eprintln!("Failed to save the embedding cache counters: {}", e);
+            }
+        }
+    }
+}
+
+fn write_record(file: &mut impl Write, model: u64, text: u64, vector: &[f32]) -> io::Result<()> {
+    file.write_all(&model.to_le_bytes())?;
+    file.write_all(&text.to_le_bytes())?;
+    file.write_all(&(vector.len() as u32).to_le_bytes())?;
+    for value in vector {
+        file.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Text hash used as the cache key. Only surrounding and repeated
+/// whitespace is ignored; case and punctuation can change an embedding.
+fn text_key(text: &str) -> u64 {
+    let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    fnv1a(normalized.as_bytes())
+}
+
+/// Wraps an embedder so identical text is only ever encoded once per model.
+pub struct CachedEmbedder {
+    inner: Box<dyn Embedder>,
+    model_key: u64,
+    cache: Option<RefCell<Cache>>,
+}
+
+impl CachedEmbedder {
+    /// Wrap `inner` with the cache in `dir`, or pass straight through when
+    /// the cache is disabled.
+    pub fn new(inner: Box<dyn Embedder>, config: &CacheConfig, dir: &Path) -> io::Result<Self> {
+        let model_key = fnv1a(inner.model_id().as_bytes());
+        let cache = if config.enabled {
+            Some(RefCell::new(Cache::open(dir, config.max_entries)?))
+        } else {
+            None
+        };
+        Ok(Self { inner, model_key, cache })
+    }
+
+    /// Encode without looking up or storing anything, for one-off text such
+    /// as search queries that would only crowd out the notes.
+    pub fn encode_uncached(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ModelError> {
+        self.inner.encode(texts)
+    }
+
+    /// Forget every vector cached for this model, so the next encode of
+    /// each text runs the model again.
+    pub fn clear(&self) -> io::Result<()> {
+        match &self.cache {
+            Some(cache) => cache.borrow_mut().clear_model(self.model_key),
+            None => Ok(()),
+        }
+    }
+
+    /// `None` when the cache is disabled.
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.borrow().stats())
+    }
+}
+
+impl Embedder for CachedEmbedder {
+    fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ModelError> {
+        let Some(cache) = &self.cache else {
+            return self.inner.encode(texts);
+        };
+        let mut cache = cache.borrow_mut();
+        let keys: Vec<u64> = texts.iter().map(|text| text_key(text)).collect();
+        // A vector of the wrong length means the model changed under the same id
+        let mut vectors: Vec<Option<Vec<f32>>> = keys
+            .iter()
+            .map(|key| cache.get(self.model_key, *key).filter(|vector| vector.len() == self.inner.dimension()))
+            .collect();
+
+        let missing: Vec<usize> = (0..texts.len()).filter(|i| vectors[*i].is_none()).collect();
+        cache.count(texts.len() - missing.len(), missing.len());
+        if !missing.is_empty() {
+            let batch: Vec<&str> = missing.iter().map(|i| texts[*i]).collect();
+            for (i, vector) in missing.iter().zip(self.inner.encode(&batch)?) {
+                cache.insert(self.model_key, keys[*i], vector.clone())?;
+                vectors[*i] = Some(vector);
+            }
+        }
+
+        Ok(vectors.into_iter().map(|vector| vector.expect("every text was cached or encoded")).collect())
+    }
+
+    fn dimension(&self) -> usize {
+        self.inner.dimension()
+    }
+
+    fn model_id(&self) -> &str {
+        self.inner.model_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_of_every_model_survive_a_reopen() {
+        let dir = std::env::temp_dir().join(format!("pad-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        {
+            let mut cache = Cache::open(&dir, 10).unwrap();
+            cache.insert(1, 10, vec![1.0; 384]).unwrap();
+            cache.insert(2, 10, vec![2.0; 512]).unwrap();
+            cache.insert(1, 11, vec![3.0; 384]).unwrap();
+        }
+        let cache = Cache::open(&dir, 10).unwrap();
+        assert_eq!(cache.records.len(), 3);
+        assert_eq!(cache.get(2, 10).map(|vector| vector.len()), Some(512));
+        assert_eq!(cache.get(1, 11), Some(vec![3.0; 384]));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
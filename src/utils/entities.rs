@@ -0,0 +1,53 @@
+use rust_bert::pipelines::ner::NERModel;
+use std::collections::BTreeMap;
+use crate::utils::config::EntitiesConfig;
+use crate::utils::metadata::{EntityKind, EntityMention, MetadataStore};
+use crate::utils::notes::Note;
+
+// Notes sent to the NER model at once
+const BATCH_SIZE: usize = 16;
+
+/// Run NER over `notes` and store the people, organizations and places found
+/// in `store`, replacing earlier results for those notes.
+pub fn extract_entities(model: &NERModel, config: &EntitiesConfig, notes: &[Note], store: &mut MetadataStore) {
+    for batch in notes.chunks(BATCH_SIZE) {
+        let texts: Vec<&str> = batch.iter().map(|note| note.text.as_str()).collect();
+        let predictions = model.predict_full_entities(&texts);
+        for (note, entities) in batch.iter().zip(predictions) {
+            let mut mentions: Vec<EntityMention> = Vec::new();
+            for entity in entities {
+                let Some(kind) = EntityKind::from_label(&entity.label) else {
+                    continue;
+                };
+                let name = entity.word.trim().to_string();
+                if entity.score < config.min_score || name.is_empty() {
+                    continue;
+                }
+                let mention = EntityMention { name, kind };
+                if !mentions.contains(&mention) {
+                    mentions.push(mention);
+                }
+            }
+            store.entry(&note.text).entities = Some(mentions);
+        }
+    }
+}
+
+/// Every entity of the given kinds with the number of notes mentioning it,
+/// most mentioned first.
+pub fn entity_counts(notes: &[Note], kinds: &[EntityKind]) -> Vec<(EntityMention, usize)> {
+    let mut counts: BTreeMap<(EntityKind, String), (String, usize)> = BTreeMap::new();
+    for note in notes {
+        for entity in note.entities.iter().filter(|entity| kinds.contains(&entity.kind)) {
+            // Group spellings that only differ in case
+            let key = (entity.kind, entity.name.to_lowercase());
+            counts.entry(key).or_insert_with(|| (entity.name.clone(), 0)).1 += 1;
+        }
+    }
+    let mut counts: Vec<(EntityMention, usize)> = counts
+        .into_iter()
+        .map(|((kind, _), (name, count))| (EntityMention { name, kind }, count))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.name.cmp(&b.0.name)));
+    counts
+}
@@ -1,18 +1,7 @@
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
 use chrono::Local;
-use dirs::home_dir;
-
-const NOTES_FILE: &str = "notes.txt";
-
-fn get_notes_path() -> PathBuf {
-    let mut path = home_dir().expect("Could not find home directory");
-    path.push("notes");
-    fs::create_dir_all(&path).expect("Failed to create notes directory");
-    path.push(NOTES_FILE);
-    path
-}
+use crate::utils::notes::{format_line, get_notes_path};
 
 pub fn write_to_file(category: Option<&str>, content: &str) -> std::io::Result<()> {
     let notes_path = get_notes_path();
@@ -24,14 +13,8 @@ pub fn write_to_file(category: Option<&str>, content: &str) -> std::io::Result<(
         .open(notes_path)?;
 
     // Write content with timestamp and category
-    let timestamp = Local::now().format("%Y-%m-%d");
+    let timestamp = Local::now().format("%Y-%m-%d").to_string();
     let category = category.unwrap_or("general");
-    writeln!(
-        file, 
-        "{:<14} {:<10} {}",
-        format!("[{}]", timestamp), 
-        format!("[{}]", category), 
-        content
-    )?;
+    writeln!(file, "{}", format_line(&timestamp, category, content))?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,131 @@
+use hnsw_rs::dist::DistCosine;
+use hnsw_rs::hnswio::{load_description, load_hnsw};
+use hnsw_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use crate::utils::config::IndexConfig;
+
+// hnsw_rs adds `.hnsw.graph` and `.hnsw.data` to this name
+const INDEX_BASENAME: &str = "index";
+const INDEX_META_FILE: &str = "index.json";
+const MAX_LAYER: usize = 16;
+const MIN_CAPACITY: usize = 256;
+
+/// The shape an HNSW graph was built with.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexParams {
+    pub m: usize,
+    pub ef_construction: usize,
+    /// Number of points the graph was sized for
+    pub capacity: usize,
+}
+
+impl IndexParams {
+    /// Parameters for a graph holding `count` points, with room to grow by
+    /// half again before it has to be rebuilt.
+    pub fn new(config: &IndexConfig, count: usize) -> Self {
+        Self {
+            m: config.m,
+            ef_construction: config.ef_construction,
+            capacity: (count + count / 2).max(MIN_CAPACITY),
+        }
+    }
+
+    pub fn build(&self) -> Hnsw<f32, DistCosine> {
+        Hnsw::new(self.m, self.capacity, MAX_LAYER, self.ef_construction, DistCosine{})
+    }
+
+    fn matches(&self, config: &IndexConfig) -> bool {
+        self.m == config.m && self.ef_construction == config.ef_construction
+    }
+}
+
+/// Written next to the dumped graph so a stale index can be detected
+/// without loading it.
+#[derive(Serialize, Deserialize)]
+struct IndexMeta {
+    checksum: u64,
+    count: usize,
+    params: IndexParams,
+}
+
+/// FNV-1a over every text and embedding, in order. Index ids are positions
+/// in the embedding list, so any change to it, including reordering,
+/// invalidates a dumped index.
+pub fn checksum<'a>(entries: impl Iterator<Item = (&'a str, &'a [f32])>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for (text, embedding) in entries {
+        feed(&(text.len() as u64).to_le_bytes());
+        feed(text.as_bytes());
+        for value in embedding {
+            feed(&value.to_bits().to_le_bytes());
+        }
+    }
+    hash
+}
+
+fn graph_path(dir: &Path) -> PathBuf {
+    dir.join(format!("{}.hnsw.graph", INDEX_BASENAME))
+}
+
+fn data_path(dir: &Path) -> PathBuf {
+    dir.join(format!("{}.hnsw.data", INDEX_BASENAME))
+}
+
+/// Reload the index dumped in `dir`, if there is one, it was built from
+/// embeddings with this checksum, and its parameters still match the
+/// config. A dump that can't be read counts as stale; hnsw_rs panics
+/// rather than returning an error on some malformed dumps, so those panics
+/// are caught too.
+pub fn load_index(dir: &Path, checksum: u64, config: &IndexConfig) -> Option<(Hnsw<f32, DistCosine>, IndexParams)> {
+    let content = fs::read_to_string(dir.join(INDEX_META_FILE)).ok()?;
+    let meta: IndexMeta = serde_json::from_str(&content).ok()?;
+    if meta.checksum != checksum || !meta.params.matches(config) {
+        return None;
+    }
+
+    let mut graph_in = BufReader::new(File::open(graph_path(dir)).ok()?);
+    let mut data_in = BufReader::new(File::open(data_path(dir)).ok()?);
+    let index = without_panicking(|| {
+        let description = load_description(&mut graph_in).ok()?;
+        load_hnsw::<f32, DistCosine>(&mut graph_in, &description, &mut data_in).ok()
+    })?;
+    (index.get_nb_point() == meta.count).then_some((index, meta.params))
+}
+
+/// Run `f`, turning a panic into `None` without printing it.
+fn without_panicking<T>(f: impl FnOnce() -> Option<T>) -> Option<T> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result.ok().flatten()
+}
+
+/// Dump the index to `dir`, recording the checksum of the embeddings it
+/// was built from. The checksum is written last, so an interrupted dump is
+/// seen as stale rather than loaded half-written.
+pub fn save_index(dir: &Path, index: &Hnsw<f32, DistCosine>, params: IndexParams, checksum: u64) -> io::Result<()> {
+    let _ = fs::remove_file(dir.join(INDEX_META_FILE));
+    if index.get_nb_point() == 0 {
+        // hnsw_rs can't dump an empty graph; with no meta file it's rebuilt
+        return Ok(());
+    }
+
+    let basename = dir.join(INDEX_BASENAME).to_string_lossy().into_owned();
+    index.file_dump(&basename).map_err(|e| io::Error::other(e.to_string()))?;
+
+    let meta = IndexMeta { checksum, count: index.get_nb_point(), params };
+    let content = serde_json::to_string(&meta)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(dir.join(INDEX_META_FILE), content)
+}
@@ -0,0 +1,133 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::utils::notes;
+
+pub const DEFAULT_CATEGORIES: [&str; 5] = ["read", "watch", "listen", "idea", "general"];
+
+/// Note text and, if the user set one in the editor, a category override.
+pub struct NoteInput {
+    pub text: String,
+    pub category: Option<String>,
+}
+
+/// Read a note from standard input.
+pub fn read_stdin() -> io::Result<NoteInput> {
+    let mut buffer = String::new();
+    io::stdin().read_to_string(&mut buffer)?;
+    Ok(NoteInput {
+        text: join_lines(buffer.lines()),
+        category: None,
+    })
+}
+
+/// Open `$VISUAL`/`$EDITOR` on a temporary file and read the note back.
+pub fn read_from_editor(category: &str) -> io::Result<NoteInput> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let (path, mut file) = create_temp_file()?;
+    file.write_all(template(category)?.as_bytes())?;
+    drop(file);
+
+    // The editor may carry arguments, e.g. EDITOR="code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(&path).status();
+    let content = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    if !status?.success() {
+        return Err(io::Error::other(format!("Editor '{}' exited with an error", editor)));
+    }
+    let input = parse_editor_content(&content?);
+    if let Some(category) = &input.category {
+        notes::check_category(category)?;
+    }
+    Ok(input)
+}
+
+/// Create a fresh file in the temp dir that only the user can read.
+/// `create_new` refuses to follow a file or symlink planted at the path,
+/// and the name isn't guessable from the pid alone.
+fn create_temp_file() -> io::Result<(PathBuf, File)> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    for attempt in 0..16u32 {
+        let mut path = env::temp_dir();
+        path.push(format!("pad-note-{}-{:08x}-{}.txt", std::process::id(), nanos, attempt));
+        match open_private(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "Could not create a temporary file for the editor"))
+}
+
+#[cfg(unix)]
+fn open_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+fn template(category: &str) -> io::Result<String> {
+    let mut categories: Vec<String> = DEFAULT_CATEGORIES.iter().map(|c| c.to_string()).collect();
+    for note in notes::load_notes()? {
+        if !note.category.is_empty() && !categories.contains(&note.category) {
+            categories.push(note.category);
+        }
+    }
+    Ok(format!(
+        "\n\
+         # Write your note above. Lines starting with '#' are ignored and\n\
+         # an empty note aborts.\n\
+         #\n\
+         # Category: {}\n\
+         # To change it, start the note with a line \"category: <name>\"\n\
+         # using one of:\n\
+         #   {}\n",
+        category,
+        categories.join(", ")
+    ))
+}
+
+/// A `category:` line only counts before the first line of note text, so
+/// note text that happens to start with it is kept.
+fn parse_editor_content(content: &str) -> NoteInput {
+    let mut category = None;
+    let mut in_header = true;
+    let lines = content.lines().filter(|line| {
+        let trimmed = line.trim();
+        if trimmed.starts_with('#') {
+            return false;
+        }
+        if in_header {
+            if let Some(value) = trimmed.strip_prefix("category:") {
+                category = Some(value.trim().to_string()).filter(|c| !c.is_empty());
+                return false;
+            }
+            in_header = trimmed.is_empty();
+        }
+        true
+    });
+    let text = join_lines(lines);
+    NoteInput { text, category }
+}
+
+/// `notes.txt` stores one note per line, so multi-line input is joined.
+fn join_lines<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    lines
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,48 @@
+use rust_bert::pipelines::keywords_extraction::KeywordExtractionModel;
+use crate::utils::config::AutotagConfig;
+use crate::utils::metadata::MetadataStore;
+use crate::utils::models::ModelError;
+use crate::utils::notes::Note;
+
+// Notes sent to the keyword model at once
+const BATCH_SIZE: usize = 32;
+
+/// Extract auto-tags for `notes` and store them in `store`, replacing any
+/// previous auto-tags of those notes. Returns how many notes were tagged.
+pub fn tag_notes(
+    model: &KeywordExtractionModel,
+    config: &AutotagConfig,
+    notes: &[Note],
+    store: &mut MetadataStore,
+) -> Result<usize, ModelError> {
+    for batch in notes.chunks(BATCH_SIZE) {
+        let texts: Vec<&str> = batch.iter().map(|note| note.text.as_str()).collect();
+        let keywords = model.predict(&texts)?;
+        for (note, keywords) in batch.iter().zip(keywords) {
+            let user_tags = note.tags();
+            let mut auto_tags: Vec<String> = Vec::new();
+            for keyword in keywords {
+                let tag = normalize_tag(&keyword.text);
+                if keyword.score < config.min_score || tag.is_empty() {
+                    continue;
+                }
+                if !user_tags.contains(&tag) && !auto_tags.contains(&tag) {
+                    auto_tags.push(tag);
+                }
+            }
+            auto_tags.truncate(config.count);
+            store.entry(&note.text).auto_tags = Some(auto_tags);
+        }
+    }
+    Ok(notes.len())
+}
+
+/// Lowercase and hyphenate a keyword so it reads like a `#tag`.
+fn normalize_tag(keyword: &str) -> String {
+    keyword
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use crate::utils::notes::Note;
+use crate::utils::semantic_search::SearchResult;
+
+// Standard Okapi BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Split text into lowercase terms. Identifiers such as `tokio-rs`, `serde_json`
+/// or `PAD-142` are kept whole and also indexed by their parts, so both the
+/// exact identifier and its pieces match.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text.split(|c: char| !(c.is_alphanumeric() || "_-.#".contains(c))) {
+        let word = word.trim_matches(|c: char| "_-.#".contains(c)).to_lowercase();
+        if word.is_empty() {
+            continue;
+        }
+        if word.contains(|c: char| "_-.".contains(c)) {
+            tokens.extend(
+                word.split(|c: char| "_-.".contains(c))
+                    .filter(|part| !part.is_empty())
+                    .map(String::from),
+            );
+        }
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// Rank notes against `query` with BM25 over their category and text.
+///
+/// The last query term also matches as a prefix, which keeps results useful
+/// while a query is still being typed in the viewer.
+pub fn search(notes: &[Note], query: &str, k: usize) -> Vec<SearchResult> {
+    let query_terms = tokenize(query);
+    if query_terms.is_empty() || notes.is_empty() {
+        return Vec::new();
+    }
+
+    let docs: Vec<Vec<String>> = notes
+        .iter()
+        .map(|note| tokenize(&format!("{} {}", note.category, note.text)))
+        .collect();
+    let avg_len = docs.iter().map(Vec::len).sum::<usize>() as f32 / docs.len() as f32;
+
+    let last = query_terms.len() - 1;
+    let term_matches = |i: usize, token: &str| {
+        token == query_terms[i] || (i == last && token.starts_with(query_terms[i].as_str()))
+    };
+
+    let mut doc_freq = vec![0usize; query_terms.len()];
+    for doc in &docs {
+        for (i, freq) in doc_freq.iter_mut().enumerate() {
+            if doc.iter().any(|token| term_matches(i, token)) {
+                *freq += 1;
+            }
+        }
+    }
+
+    let n = docs.len() as f32;
+    let mut scored: Vec<(usize, f32)> = docs
+        .iter()
+        .enumerate()
+        .map(|(doc_index, doc)| {
+            let mut counts: HashMap<usize, f32> = HashMap::new();
+            for token in doc {
+                for i in 0..query_terms.len() {
+                    if term_matches(i, token) {
+                        *counts.entry(i).or_default() += 1.0;
+                    }
+                }
+            }
+            let doc_len = doc.len() as f32;
+            let score = counts
+                .into_iter()
+                .map(|(i, tf)| {
+                    let df = doc_freq[i] as f32;
+                    let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * doc_len / avg_len.max(1.0)))
+                })
+                .sum();
+            (doc_index, score)
+        })
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored
+        .into_iter()
+        .take(k)
+        .map(|(i, score)| SearchResult { note: notes[i].clone(), score })
+        .collect()
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+
+const METADATA_FILE: &str = "metadata.json";
+
+/// Generated information about a note, kept apart from `notes.txt` so the
+/// notes file stays hand-editable.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoteMetadata {
+    /// Tags extracted by the keyword model, distinct from `#tags` typed in
+    /// the note; `None` until the note has been processed
+    pub auto_tags: Option<Vec<String>>,
+    /// People, organizations and places found by the NER model; `None`
+    /// until the note has been processed
+    pub entities: Option<Vec<EntityMention>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind {
+    Person,
+    Organization,
+    Location,
+}
+
+impl EntityKind {
+    /// Map a CoNLL label (`PER`, `ORG`, `LOC`, optionally with a `B-`/`I-`
+    /// prefix) to a kind. Other labels such as `MISC` aren't recorded.
+    pub fn from_label(label: &str) -> Option<EntityKind> {
+        match label.trim_start_matches("B-").trim_start_matches("I-") {
+            "PER" => Some(EntityKind::Person),
+            "ORG" => Some(EntityKind::Organization),
+            "LOC" => Some(EntityKind::Location),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EntityKind::Person => "person",
+            EntityKind::Organization => "organization",
+            EntityKind::Location => "location",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityMention {
+    pub name: String,
+    pub kind: EntityKind,
+}
+
+/// All note metadata, keyed by note text like the embeddings are.
+#[derive(Default)]
+pub struct MetadataStore {
+    pub entries: HashMap<String, NoteMetadata>,
+}
+
+impl MetadataStore {
+    fn path() -> PathBuf {
+        let mut path = home_dir().expect("Could not find home directory");
+        path.push("notes");
+        fs::create_dir_all(&path).expect("Failed to create notes directory");
+        path.push(METADATA_FILE);
+        path
+    }
+
+    pub fn load() -> io::Result<MetadataStore> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(MetadataStore::default());
+        }
+        let content = fs::read_to_string(path)?;
+        let entries = serde_json::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(MetadataStore { entries })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let content = serde_json::to_string(&self.entries)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(Self::path(), content)
+    }
+
+    pub fn get(&self, text: &str) -> Option<&NoteMetadata> {
+        self.entries.get(text)
+    }
+
+    pub fn entry(&mut self, text: &str) -> &mut NoteMetadata {
+        self.entries.entry(text.to_string()).or_default()
+    }
+}
@@ -1,3 +1,26 @@
+pub mod ask;
+pub mod categorize;
+pub mod config;
+pub mod dedupe;
+pub mod digest;
+pub mod doctor;
+pub mod embedder;
+pub mod embedding_cache;
+pub mod entities;
 pub mod file_writing;
+pub mod index;
+pub mod input;
+pub mod keywords;
+pub mod lexical;
+pub mod metadata;
+pub mod models;
+pub mod notes;
+pub mod output;
+pub mod quantize;
+pub mod query;
+pub mod reembed;
+pub mod search;
 pub mod viewer;
-pub mod semantic_search; 
\ No newline at end of file
+pub mod semantic_search;
+pub mod topics;
+pub mod vector_store; 
\ No newline at end of file
@@ -0,0 +1,350 @@
+use clap::ValueEnum;
+use rust_bert::pipelines::common::{ModelResource, ModelType};
+use rust_bert::pipelines::keywords_extraction::{KeywordExtractionConfig, KeywordExtractionModel};
+use rust_bert::pipelines::ner::NERModel;
+use rust_bert::pipelines::question_answering::{QuestionAnsweringConfig, QuestionAnsweringModel};
+use rust_bert::pipelines::sentence_embeddings::{
+    SentenceEmbeddingsBuilder, SentenceEmbeddingsConfig, SentenceEmbeddingsModel, SentenceEmbeddingsModulesConfig,
+};
+use rust_bert::pipelines::summarization::{SummarizationConfig, SummarizationModel};
+use rust_bert::pipelines::token_classification::{LabelAggregationOption, TokenClassificationConfig};
+use rust_bert::resources::LocalResource;
+use rust_bert::{Config as _, RustBertError};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use dirs::home_dir;
+use thiserror::Error;
+use crate::utils::config::Config;
+
+#[derive(Error, Debug)]
+pub enum ModelError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Model error: {0}")]
+    Model(#[from] RustBertError),
+    #[error(
+        "The {} model is missing {missing} in {}.\n\
+         Download a rust-bert compatible model ({}) on a machine with network access, then \
+         run `pad model install {} --from <dir>`, or point `models.{}` in ~/notes/config.json \
+         at a directory that has the files.",
+        .kind.name(), .dir.display(), .kind.files().join(", "), .kind.cli_name(), .kind.key()
+    )]
+    Missing {
+        kind: ModelKind,
+        dir: PathBuf,
+        missing: String,
+    },
+    #[error(
+        "Could not download the sentence embedding model: {source}\n\
+         Without network access, copy a rust-bert compatible {model} ({}) from another machine \
+         and run `pad model install embeddings --from <dir>`.",
+        ModelKind::Embeddings.files().join(", ")
+    )]
+    Download {
+        model: &'static str,
+        #[source]
+        source: RustBertError,
+    },
+    #[error("This build of pad doesn't support {0}")]
+    Unsupported(&'static str),
+    #[cfg(feature = "onnx")]
+    #[error("Tokenizer error: {0}")]
+    Tokenizer(String),
+    #[cfg(feature = "onnx")]
+    #[error("ONNX Runtime error: {0}")]
+    Onnx(#[from] ort::Error),
+}
+
+/// The local models pad can use.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ModelKind {
+    /// Sentence embeddings for semantic search
+    Embeddings,
+    /// BART summarizer for `pad digest`
+    Summarization,
+    /// Extractive question answering for `pad ask`
+    QuestionAnswering,
+    /// Named-entity recognition for `pad extract-entities`
+    Ner,
+    /// Sentence embeddings exported to ONNX, for the `onnx` embedding backend
+    Onnx,
+}
+
+impl ModelKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ModelKind::Embeddings => "sentence embedding",
+            ModelKind::Summarization => "summarization",
+            ModelKind::QuestionAnswering => "question answering",
+            ModelKind::Ner => "named entity recognition",
+            ModelKind::Onnx => "ONNX sentence embedding",
+        }
+    }
+
+    fn cli_name(&self) -> &'static str {
+        match self {
+            ModelKind::QuestionAnswering => "question-answering",
+            _ => self.key(),
+        }
+    }
+
+    /// Key under `models` in the config, and the default directory name.
+    pub fn key(&self) -> &'static str {
+        match self {
+            ModelKind::Embeddings => "embeddings",
+            ModelKind::Summarization => "summarization",
+            ModelKind::QuestionAnswering => "question_answering",
+            ModelKind::Ner => "ner",
+            ModelKind::Onnx => "onnx",
+        }
+    }
+
+    /// Files that must be present for the model to load.
+    pub fn files(&self) -> &'static [&'static str] {
+        match self {
+            ModelKind::Embeddings => &[
+                "modules.json",
+                "config.json",
+                "rust_model.ot",
+                "sentence_bert_config.json",
+                "tokenizer_config.json",
+                "vocab.txt",
+                "1_Pooling/config.json",
+            ],
+            ModelKind::Summarization => &["rust_model.ot", "config.json", "vocab.json", "merges.txt"],
+            ModelKind::QuestionAnswering | ModelKind::Ner => &["rust_model.ot", "config.json", "vocab.txt"],
+            ModelKind::Onnx => &["model.onnx", "tokenizer.json"],
+        }
+    }
+
+    /// Directory for the model: the configured path if set, otherwise
+    /// `~/notes/models/<key>`, with a subdirectory per embedding model.
+    pub fn dir(&self, config: &Config) -> PathBuf {
+        let configured = match self {
+            ModelKind::Embeddings => config.models.embeddings.as_ref(),
+            ModelKind::Summarization => config.models.summarization.as_ref(),
+            ModelKind::QuestionAnswering => config.models.question_answering.as_ref(),
+            ModelKind::Ner => config.models.ner.as_ref(),
+            ModelKind::Onnx => config.models.onnx.as_ref(),
+        };
+        configured.cloned().unwrap_or_else(|| match self {
+            ModelKind::Embeddings => models_dir().join(self.key()).join(config.embedding.model.id()),
+            _ => models_dir().join(self.key()),
+        })
+    }
+}
+
+/// `~/notes/models`, where local models live by default.
+pub fn models_dir() -> PathBuf {
+    let mut path = home_dir().expect("Could not find home directory");
+    path.push("notes");
+    path.push("models");
+    path
+}
+
+/// Fail with instructions unless every file `kind` needs is present in
+/// `dir`.
+pub fn require_files(kind: ModelKind, dir: &Path) -> Result<(), ModelError> {
+    let missing: Vec<&str> = kind
+        .files()
+        .iter()
+        .copied()
+        .filter(|file| !dir.join(file).is_file())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(ModelError::Missing {
+        kind,
+        dir: dir.to_path_buf(),
+        missing: missing.join(", "),
+    })
+}
+
+/// Copy a model from `from` into its directory, checking that every file it
+/// needs is there beforehand and arrived intact, then load it once to make
+/// sure it works. Returns the directory it was installed to.
+pub fn install_model(kind: ModelKind, from: &Path, config: &Config) -> Result<PathBuf, ModelError> {
+    require_files(kind, from)?;
+    let dir = kind.dir(config);
+    copy_dir(from, &dir)?;
+
+    for file in kind.files() {
+        let (source, copy) = (fs::metadata(from.join(file))?, fs::metadata(dir.join(file))?);
+        if source.len() != copy.len() {
+            return Err(ModelError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} was not copied completely", dir.join(file).display()),
+            )));
+        }
+    }
+
+    match kind {
+        ModelKind::Embeddings => load_embedding_model(config).map(drop)?,
+        ModelKind::Summarization => load_summarization_model(config).map(drop)?,
+        ModelKind::QuestionAnswering => load_question_answering_model(config).map(drop)?,
+        ModelKind::Ner => load_ner_model(config).map(drop)?,
+        ModelKind::Onnx => {
+            // Without the feature the files can be installed but not loaded
+            #[cfg(feature = "onnx")]
+            crate::utils::embedder::load_onnx_embedder(config).map(drop)?;
+        }
+    }
+    Ok(dir)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Load the sentence embedding model used for semantic search. A model in
+/// the local directory (or one configured with `models.embeddings`) is
+/// used as is; otherwise the selected model is downloaded on first use.
+pub fn load_embedding_model(config: &Config) -> Result<SentenceEmbeddingsModel, ModelError> {
+    if let Some(dir) = local_embeddings_dir(config) {
+        require_files(ModelKind::Embeddings, &dir)?;
+        return Ok(SentenceEmbeddingsBuilder::local(&dir).create_model()?);
+    }
+    let model = config.embedding.model;
+    SentenceEmbeddingsBuilder::remote(model.model_type())
+        .create_model()
+        .map_err(|source| ModelError::Download { model: model.id(), source })
+}
+
+/// The embedding model directory, if the model should be loaded from disk
+/// rather than downloaded.
+fn local_embeddings_dir(config: &Config) -> Option<PathBuf> {
+    let dir = ModelKind::Embeddings.dir(config);
+    (config.models.embeddings.is_some() || dir.exists()).then_some(dir)
+}
+
+// The part of a transformer's config.json needed to find its vocabulary
+#[derive(Deserialize)]
+struct TransformerConfig {
+    model_type: ModelType,
+}
+
+impl rust_bert::Config for TransformerConfig {}
+
+/// Resources for the embedding model `load_embedding_model` would load,
+/// for pipelines that build their own `SentenceEmbeddingsModel`. A local
+/// directory is read the way `SentenceEmbeddingsBuilder::local` reads it.
+fn sentence_embeddings_config(config: &Config) -> Result<SentenceEmbeddingsConfig, ModelError> {
+    let Some(dir) = local_embeddings_dir(config) else {
+        return Ok(config.embedding.model.model_type().into());
+    };
+    require_files(ModelKind::Embeddings, &dir)?;
+
+    let modules = SentenceEmbeddingsModulesConfig::from_file(dir.join("modules.json")).validate()?;
+    let transformer_type = TransformerConfig::from_file(dir.join("config.json")).model_type;
+    if !matches!(transformer_type, ModelType::Bert | ModelType::DistilBert) {
+        return Err(ModelError::Unsupported("keyword extraction with an embedding model that has no vocab.txt"));
+    }
+    let dense = modules.dense_module().map(|module| dir.join(&module.path));
+    let resource = |path: PathBuf| -> Box<dyn rust_bert::resources::ResourceProvider + Send> {
+        Box::new(LocalResource::from(path))
+    };
+    Ok(SentenceEmbeddingsConfig {
+        modules_config_resource: resource(dir.join("modules.json")),
+        transformer_type,
+        transformer_config_resource: resource(dir.join("config.json")),
+        transformer_weights_resource: resource(dir.join("rust_model.ot")),
+        pooling_config_resource: resource(dir.join(&modules.pooling_module().path).join("config.json")),
+        dense_config_resource: dense.as_ref().map(|dense| resource(dense.join("config.json"))),
+        dense_weights_resource: dense.as_ref().map(|dense| resource(dense.join("rust_model.ot"))),
+        sentence_bert_config_resource: resource(dir.join("sentence_bert_config.json")),
+        tokenizer_config_resource: resource(dir.join("tokenizer_config.json")),
+        tokenizer_vocab_resource: resource(dir.join("vocab.txt")),
+        tokenizer_merges_resource: None,
+        device: tch::Device::cuda_if_available(),
+        kind: None,
+    })
+}
+
+/// Load a BART summarization model from a local directory, so digests work
+/// offline.
+pub fn load_summarization_model(config: &Config) -> Result<SummarizationModel, ModelError> {
+    let dir = ModelKind::Summarization.dir(config);
+    require_files(ModelKind::Summarization, &dir)?;
+
+    let summarization_config = SummarizationConfig::new(
+        ModelType::Bart,
+        ModelResource::Torch(Box::new(LocalResource::from(dir.join("rust_model.ot")))),
+        LocalResource::from(dir.join("config.json")),
+        LocalResource::from(dir.join("vocab.json")),
+        Some(LocalResource::from(dir.join("merges.txt"))),
+    );
+    Ok(SummarizationModel::new(summarization_config)?)
+}
+
+/// Load a DistilBERT question-answering model (e.g. one fine-tuned on SQuAD)
+/// from a local directory.
+pub fn load_question_answering_model(config: &Config) -> Result<QuestionAnsweringModel, ModelError> {
+    let dir = ModelKind::QuestionAnswering.dir(config);
+    require_files(ModelKind::QuestionAnswering, &dir)?;
+
+    let qa_config = QuestionAnsweringConfig::new(
+        ModelType::DistilBert,
+        ModelResource::Torch(Box::new(LocalResource::from(dir.join("rust_model.ot")))),
+        LocalResource::from(dir.join("config.json")),
+        LocalResource::from(dir.join("vocab.txt")),
+        None,
+        false,
+        None::<bool>,
+        None::<bool>,
+    );
+    Ok(QuestionAnsweringModel::new(qa_config)?)
+}
+
+/// Load the keyword extraction pipeline, which runs on the same
+/// sentence-embedding model as the rust-bert search backend, from the same
+/// local directory when there is one.
+pub fn load_keyword_model(config: &Config) -> Result<KeywordExtractionModel<'static>, ModelError> {
+    let local = local_embeddings_dir(config).is_some();
+    let keyword_config = KeywordExtractionConfig {
+        sentence_embeddings_config: sentence_embeddings_config(config)?,
+        // Extra candidates, since low scores and user tags get filtered out
+        num_keywords: config.autotag.count * 2,
+        ..Default::default()
+    };
+    let model = config.embedding.model;
+    KeywordExtractionModel::new(keyword_config).map_err(|source| {
+        if local {
+            ModelError::Model(source)
+        } else {
+            ModelError::Download { model: model.id(), source }
+        }
+    })
+}
+
+/// Load a BERT named-entity recognition model (CoNLL-03 labels) from a local
+/// directory.
+pub fn load_ner_model(config: &Config) -> Result<NERModel, ModelError> {
+    let dir = ModelKind::Ner.dir(config);
+    require_files(ModelKind::Ner, &dir)?;
+
+    let ner_config = TokenClassificationConfig::new(
+        ModelType::Bert,
+        ModelResource::Torch(Box::new(LocalResource::from(dir.join("rust_model.ot")))),
+        LocalResource::from(dir.join("config.json")),
+        LocalResource::from(dir.join("vocab.txt")),
+        None,
+        false,
+        None::<bool>,
+        None::<bool>,
+        LabelAggregationOption::Mode,
+    );
+    Ok(NERModel::new(ner_config)?)
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use chrono::{Days, Local, Months, NaiveDate};
+use clap::Args;
+use dirs::home_dir;
+use serde::Serialize;
+use crate::utils::metadata::{EntityMention, MetadataStore};
+use crate::utils::query::Query;
+
+const NOTES_FILE: &str = "notes.txt";
+
+/// A single line of `notes.txt`, parsed into its parts.
+///
+/// `id` is the 1-based position of the note in the file, which is what the
+/// CLI commands accept as a note identifier.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Note {
+    pub id: usize,
+    pub date: String,
+    pub category: String,
+    pub text: String,
+    /// Tags from the keyword model, stored in `metadata.json`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub auto_tags: Vec<String>,
+    /// Named entities from the NER model, stored in `metadata.json`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<EntityMention>,
+}
+
+impl Note {
+    /// Parse a `[date] [category] text` line. Lines that don't follow the
+    /// format are kept as plain text so saving never drops them.
+    pub fn parse(id: usize, line: &str) -> Note {
+        let mut parts = line.splitn(3, ']');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(date), Some(category), Some(text)) if date.trim_start().starts_with('[') => Note {
+                id,
+                date: date.trim().trim_start_matches('[').to_string(),
+                category: category.trim().trim_start_matches('[').to_string(),
+                text: text.trim().to_string(),
+                auto_tags: Vec::new(),
+                entities: Vec::new(),
+            },
+            _ => Note {
+                id,
+                date: String::new(),
+                category: String::new(),
+                text: line.trim().to_string(),
+                auto_tags: Vec::new(),
+                entities: Vec::new(),
+            },
+        }
+    }
+
+    /// Format the note the same way `write_to_file` does.
+    pub fn to_line(&self) -> String {
+        if self.date.is_empty() && self.category.is_empty() {
+            return self.text.clone();
+        }
+        format_line(&self.date, &self.category, &self.text)
+    }
+
+    /// `#hashtags` written in the note text, lowercased and without the `#`.
+    pub fn tags(&self) -> Vec<String> {
+        self.text
+            .split_whitespace()
+            .filter_map(|word| word.strip_prefix('#'))
+            .map(|tag| tag.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '_'))
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    /// Whether the note mentions an entity with this name (case-insensitive).
+    pub fn mentions(&self, name: &str) -> bool {
+        self.entities.iter().any(|entity| entity.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn parsed_date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").ok()
+    }
+}
+
+/// Restrict a command to notes by category, tag and date range.
+#[derive(Args, Clone, Debug, Default)]
+pub struct NoteFilter {
+    /// Only include notes in this category
+    #[arg(short = 'c', long = "category")]
+    pub category: Option<String>,
+
+    /// Only include notes tagged with #TAG (repeatable, all must match)
+    #[arg(short = 't', long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Only include notes with this extracted auto-tag (repeatable)
+    #[arg(long = "auto-tag")]
+    pub auto_tags: Vec<String>,
+
+    /// Only include notes mentioning this person, organization or place
+    #[arg(long = "entity")]
+    pub entity: Option<String>,
+
+    /// Only include notes on or after this date (YYYY-MM-DD, or 7d/2w/3m ago)
+    #[arg(long = "since", value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+
+    /// Only include notes on or before this date (YYYY-MM-DD, or 7d/2w/3m ago)
+    #[arg(long = "until", value_parser = parse_date)]
+    pub until: Option<NaiveDate>,
+
+    /// Structured constraints from a search query, set by the caller
+    #[arg(skip)]
+    pub query: Query,
+}
+
+impl NoteFilter {
+    pub fn is_empty(&self) -> bool {
+        self.category.is_none()
+            && self.tags.is_empty()
+            && self.auto_tags.is_empty()
+            && self.entity.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && !self.query.has_filters()
+    }
+
+    pub fn matches(&self, note: &Note) -> bool {
+        if let Some(category) = &self.category {
+            if !note.category.eq_ignore_ascii_case(category) {
+                return false;
+            }
+        }
+        if !self.tags.is_empty() {
+            let note_tags = note.tags();
+            let wanted = self.tags.iter().map(|t| t.trim_start_matches('#').to_lowercase());
+            if !wanted.into_iter().all(|t| note_tags.contains(&t)) {
+                return false;
+            }
+        }
+        if !self.auto_tags.iter().all(|tag| note.auto_tags.contains(&tag.to_lowercase())) {
+            return false;
+        }
+        if let Some(entity) = &self.entity {
+            if !note.mentions(entity) {
+                return false;
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Some(date) = note.parsed_date() else {
+                return false;
+            };
+            if self.since.is_some_and(|since| date < since) || self.until.is_some_and(|until| date > until) {
+                return false;
+            }
+        }
+        self.query.matches_filters(note)
+    }
+
+    /// Short description for headers, e.g. `category:idea tag:rust`.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(category) = &self.category {
+            parts.push(format!("category:{}", category));
+        }
+        for tag in &self.tags {
+            parts.push(format!("tag:{}", tag.trim_start_matches('#')));
+        }
+        for tag in &self.auto_tags {
+            parts.push(format!("autotag:{}", tag));
+        }
+        if let Some(entity) = &self.entity {
+            parts.push(format!("entity:{}", entity));
+        }
+        if let Some(since) = self.since {
+            parts.push(format!("since:{}", since));
+        }
+        if let Some(until) = self.until {
+            parts.push(format!("until:{}", until));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Parse an absolute `YYYY-MM-DD` date or a relative one like `7d`, `2w` or
+/// `3m` (days, weeks, months before today).
+pub fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date);
+    }
+    let invalid = || format!("invalid date '{}': expected YYYY-MM-DD or e.g. 7d, 2w, 3m", value);
+    // The unit is split off as a suffix, never by byte offset, so a
+    // trailing multi-byte character is just an invalid date
+    let (amount, unit) = ['d', 'w', 'm']
+        .into_iter()
+        .find_map(|unit| value.strip_suffix(unit).map(|amount| (amount, unit)))
+        .ok_or_else(invalid)?;
+    let amount: u32 = amount.parse().map_err(|_| invalid())?;
+    let today = Local::now().date_naive();
+    match unit {
+        'd' => today.checked_sub_days(Days::new(amount.into())),
+        'w' => today.checked_sub_days(Days::new(u64::from(amount) * 7)),
+        _ => today.checked_sub_months(Months::new(amount)),
+    }
+    .ok_or_else(|| format!("date '{}' is out of range", value))
+}
+
+/// Reject a category that `Note::parse` couldn't read back: empty, or
+/// containing brackets or whitespace.
+pub fn check_category(category: &str) -> io::Result<()> {
+    if category.is_empty() || category.contains(|c: char| c == '[' || c == ']' || c.is_whitespace()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid category '{}': use a single word without brackets", category),
+        ));
+    }
+    Ok(())
+}
+
+pub fn format_line(date: &str, category: &str, text: &str) -> String {
+    format!(
+        "{:<14} {:<10} {}",
+        format!("[{}]", date),
+        format!("[{}]", category),
+        text
+    )
+}
+
+pub fn get_notes_path() -> PathBuf {
+    let mut path = home_dir().expect("Could not find home directory");
+    path.push("notes");
+    fs::create_dir_all(&path).expect("Failed to create notes directory");
+    path.push(NOTES_FILE);
+    path
+}
+
+/// Read every note in file order, with its stored metadata attached.
+pub fn load_notes() -> io::Result<Vec<Note>> {
+    let notes_path = get_notes_path();
+    if !notes_path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(notes_path)?;
+    let metadata = MetadataStore::load()?;
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            let mut note = Note::parse(i + 1, line);
+            if let Some(meta) = metadata.get(&note.text) {
+                note.auto_tags = meta.auto_tags.clone().unwrap_or_default();
+                note.entities = meta.entities.clone().unwrap_or_default();
+            }
+            note
+        })
+        .collect())
+}
+
+/// Overwrite `notes.txt` with the given notes, in order.
+pub fn save_notes(notes: &[Note]) -> io::Result<()> {
+    let mut content: String = notes
+        .iter()
+        .map(|note| note.to_line())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    fs::write(get_notes_path(), content)
+}
+
+/// Resolve the notes targeted by a command: explicit IDs, a filter query, or
+/// both (in which case a note must satisfy either). An empty filter would
+/// select every note, so it is rejected.
+pub fn select_notes<'a>(notes: &'a [Note], ids: &[usize], filter: Option<&Query>) -> io::Result<Vec<&'a Note>> {
+    if filter.is_some_and(Query::is_empty) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The filter is empty and would select every note",
+        ));
+    }
+    for id in ids {
+        if *id == 0 || *id > notes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No note with id {}", id),
+            ));
+        }
+    }
+    Ok(notes
+        .iter()
+        .filter(|note| ids.contains(&note.id) || filter.is_some_and(|query| query.matches(note)))
+        .collect())
+}
+
+/// Remove the notes with the given IDs from `notes.txt` and return them.
+pub fn delete_notes(ids: &[usize]) -> io::Result<Vec<Note>> {
+    let notes = load_notes()?;
+    let (removed, kept): (Vec<Note>, Vec<Note>) = notes
+        .into_iter()
+        .partition(|note| ids.contains(&note.id));
+    if !removed.is_empty() {
+        save_notes(&kept)?;
+    }
+    Ok(removed)
+}
+
+/// Change the category of the notes with the given IDs.
+pub fn move_notes(ids: &[usize], category: &str) -> io::Result<usize> {
+    let mut notes = load_notes()?;
+    let mut moved = 0;
+    for note in notes.iter_mut().filter(|note| ids.contains(&note.id)) {
+        note.category = category.to_string();
+        moved += 1;
+    }
+    if moved > 0 {
+        save_notes(&notes)?;
+    }
+    Ok(moved)
+}
+
+/// Replace the text of a note, returning the note as it was before.
+pub fn replace_note_text(id: usize, text: &str) -> io::Result<Option<Note>> {
+    let mut notes = load_notes()?;
+    let Some(note) = notes.iter_mut().find(|note| note.id == id) else {
+        return Ok(None);
+    };
+    let previous = note.clone();
+    note.text = text.to_string();
+    save_notes(&notes)?;
+    Ok(Some(previous))
+}
+
+/// Move a note to the end of the file with today's date, making it the most
+/// recent note again.
+pub fn bump_note(id: usize) -> io::Result<Option<Note>> {
+    let mut notes = load_notes()?;
+    let Some(position) = notes.iter().position(|note| note.id == id) else {
+        return Ok(None);
+    };
+    let mut note = notes.remove(position);
+    note.date = Local::now().format("%Y-%m-%d").to_string();
+    notes.push(note.clone());
+    save_notes(&notes)?;
+    Ok(Some(note))
+}
@@ -0,0 +1,64 @@
+use clap::ValueEnum;
+use std::io;
+use crate::utils::semantic_search::SearchResult;
+
+/// How `pad search` prints its results.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable numbered list
+    #[default]
+    Text,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// Tab-separated id, date, category, score, text
+    Tsv,
+}
+
+/// Print results; `heading` is only shown in the human-readable format.
+pub fn print_results(heading: &str, results: &[SearchResult], format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            println!("\n{}", heading);
+            println!("----------------------------------------");
+            for (i, result) in results.iter().enumerate() {
+                println!(
+                    "{}. #{} [{}] [{}] {} (score: {:.4})",
+                    i + 1,
+                    result.note.id,
+                    result.note.date,
+                    result.note.category,
+                    result.note.text,
+                    result.score
+                );
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(results).map_err(to_io)?);
+        }
+        OutputFormat::Jsonl => {
+            for result in results {
+                println!("{}", serde_json::to_string(result).map_err(to_io)?);
+            }
+        }
+        OutputFormat::Tsv => {
+            println!("id\tdate\tcategory\tscore\ttext");
+            for result in results {
+                println!(
+                    "{}\t{}\t{}\t{:.4}\t{}",
+                    result.note.id,
+                    result.note.date,
+                    result.note.category,
+                    result.score,
+                    result.note.text.replace('\t', " ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_io(e: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    /// Search the HNSW graph over full-precision vectors
+    #[default]
+    None,
+    /// One signed byte per dimension, a quarter of the size
+    Int8,
+    /// One bit per dimension (the sign), a thirty-second of the size
+    Binary,
+}
+
+impl Quantization {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Quantization::None => "none",
+            Quantization::Int8 => "int8",
+            Quantization::Binary => "binary",
+        }
+    }
+}
+
+/// Compressed copies of unit-length embeddings, scanned in full to find
+/// candidates that are then rescored at full precision.
+pub enum QuantizedVectors {
+    Int8 { dimension: usize, codes: Vec<i8> },
+    Binary { words: usize, bits: Vec<u64> },
+}
+
+impl QuantizedVectors {
+    /// Quantize `vectors`, or `None` when quantization is off.
+    pub fn new<'a>(quantization: Quantization, dimension: usize, vectors: impl Iterator<Item = &'a [f32]>) -> Option<Self> {
+        let mut quantized = match quantization {
+            Quantization::None => return None,
+            Quantization::Int8 => QuantizedVectors::Int8 { dimension, codes: Vec::new() },
+            Quantization::Binary => QuantizedVectors::Binary { words: dimension.div_ceil(64), bits: Vec::new() },
+        };
+        for vector in vectors {
+            quantized.push(vector);
+        }
+        Some(quantized)
+    }
+
+    pub fn push(&mut self, vector: &[f32]) {
+        match self {
+            QuantizedVectors::Int8 { codes, .. } => codes.extend(int8_codes(vector)),
+            QuantizedVectors::Binary { bits, .. } => bits.extend(sign_bits(vector)),
+        }
+    }
+
+    /// Bytes taken by the quantized vectors.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            QuantizedVectors::Int8 { codes, .. } => codes.len(),
+            QuantizedVectors::Binary { bits, .. } => bits.len() * 8,
+        }
+    }
+
+    /// Ids of the `n` vectors with the highest approximate similarity to
+    /// `query`, best first, considering only `allowed` ids when given.
+    pub fn nearest(&self, query: &[f32], n: usize, allowed: Option<&[usize]>) -> Vec<usize> {
+        let mut scored: Vec<(usize, i64)> = match self {
+            QuantizedVectors::Int8 { dimension, codes } => {
+                let query = int8_codes(query);
+                let score = |id: usize| {
+                    codes[id * dimension..(id + 1) * dimension]
+                        .iter()
+                        .zip(&query)
+                        .map(|(a, b)| *a as i64 * *b as i64)
+                        .sum::<i64>()
+                };
+                candidates(codes.len() / (*dimension).max(1), allowed).map(|id| (id, score(id))).collect()
+            }
+            QuantizedVectors::Binary { words, bits, .. } => {
+                let query = sign_bits(query);
+                // Fewer differing signs means more similar
+                let score = |id: usize| {
+                    -(bits[id * words..(id + 1) * words]
+                        .iter()
+                        .zip(&query)
+                        .map(|(a, b)| (a ^ b).count_ones() as i64)
+                        .sum::<i64>())
+                };
+                candidates(bits.len() / (*words).max(1), allowed).map(|id| (id, score(id))).collect()
+            }
+        };
+        scored.sort_by_key(|(_, score)| Reverse(*score));
+        scored.into_iter().take(n).map(|(id, _)| id).collect()
+    }
+}
+
+fn candidates(count: usize, allowed: Option<&[usize]>) -> Box<dyn Iterator<Item = usize> + '_> {
+    match allowed {
+        Some(allowed) => Box::new(allowed.iter().copied().filter(move |id| *id < count)),
+        None => Box::new(0..count),
+    }
+}
+
+// Components of a unit vector lie in [-1, 1]
+fn int8_codes(vector: &[f32]) -> Vec<i8> {
+    vector.iter().map(|v| (v.clamp(-1.0, 1.0) * 127.0).round() as i8).collect()
+}
+
+fn sign_bits(vector: &[f32]) -> Vec<u64> {
+    let mut words = vec![0u64; vector.len().div_ceil(64)];
+    for (i, value) in vector.iter().enumerate() {
+        if *value > 0.0 {
+            words[i / 64] |= 1 << (i % 64);
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit vectors with distinct sign patterns, so binary codes tell them apart
+    const VECTORS: [[f32; 4]; 3] = [
+        [0.5, 0.5, 0.5, 0.5],
+        [0.5, 0.5, -0.5, -0.5],
+        [-0.5, -0.5, 0.5, 0.5],
+    ];
+    const QUERY: [f32; 4] = [0.6, 0.4, -0.5, -0.48];
+
+    fn quantized(quantization: Quantization) -> QuantizedVectors {
+        QuantizedVectors::new(quantization, 4, VECTORS.iter().map(|v| v.as_slice())).unwrap()
+    }
+
+    #[test]
+    fn no_quantization_has_no_vectors() {
+        assert!(QuantizedVectors::new(Quantization::None, 4, VECTORS.iter().map(|v| v.as_slice())).is_none());
+    }
+
+    #[test]
+    fn nearest_ranks_the_closest_vector_first() {
+        for quantization in [Quantization::Int8, Quantization::Binary] {
+            let nearest = quantized(quantization).nearest(&QUERY, 3, None);
+            assert_eq!(nearest.len(), 3, "{}", quantization.label());
+            assert_eq!(nearest[0], 1, "{}", quantization.label());
+        }
+    }
+
+    #[test]
+    fn nearest_returns_at_most_n() {
+        assert_eq!(quantized(Quantization::Int8).nearest(&QUERY, 2, None), [1, 0]);
+    }
+
+    #[test]
+    fn nearest_only_considers_allowed_ids() {
+        for quantization in [Quantization::Int8, Quantization::Binary] {
+            let nearest = quantized(quantization).nearest(&QUERY, 3, Some(&[0, 2, 7]));
+            assert!(!nearest.contains(&1), "{}", quantization.label());
+            // Ids past the end are ignored
+            assert!(!nearest.contains(&7), "{}", quantization.label());
+        }
+    }
+
+    #[test]
+    fn push_adds_a_searchable_vector() {
+        let mut vectors = quantized(Quantization::Int8);
+        let size = vectors.size_bytes();
+        vectors.push(&QUERY);
+        assert_eq!(vectors.size_bytes(), size + 4);
+        assert_eq!(vectors.nearest(&QUERY, 1, None), [3]);
+    }
+}
@@ -0,0 +1,315 @@
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use crate::utils::notes::{parse_date, Note};
+
+/// A parsed query such as
+/// `category:read tag:rust after:2026-01-01 "exact phrase" -exclude /regex/`.
+///
+/// Every clause must hold for a note to match.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub clauses: Vec<Clause>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Clause {
+    pub negated: bool,
+    pub term: Term,
+}
+
+#[derive(Clone, Debug)]
+pub enum Term {
+    /// Bare word, matched case-insensitively against category and text
+    Word(String),
+    /// `"quoted text"`, matched case-insensitively against the text
+    Phrase(String),
+    /// `/pattern/`, matched case-insensitively against the text
+    Regex(Regex),
+    /// `category:NAME`
+    Category(String),
+    /// `tag:NAME`, matching `#NAME` in the text
+    Tag(String),
+    /// `autotag:NAME`, matching a tag extracted by the keyword model
+    AutoTag(String),
+    /// `entity:NAME`, matching a person, organization or place found by NER
+    Entity(String),
+    /// `after:DATE` (inclusive)
+    After(NaiveDate),
+    /// `before:DATE` (inclusive)
+    Before(NaiveDate),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Character offset in the query where the problem starts
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at column {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, ParseError> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut clauses = Vec::new();
+        let mut pos = 0;
+
+        while pos < chars.len() {
+            if chars[pos].is_whitespace() {
+                pos += 1;
+                continue;
+            }
+            let start = pos;
+            let negated = chars[pos] == '-';
+            if negated {
+                pos += 1;
+                if pos >= chars.len() || chars[pos].is_whitespace() {
+                    return Err(error(start, "'-' must be followed by a term to exclude"));
+                }
+            }
+
+            let term = match chars[pos] {
+                '"' => {
+                    let (text, end) = read_delimited(&chars, pos, '"')
+                        .ok_or_else(|| error(pos, "unterminated quote"))?;
+                    pos = end;
+                    if text.trim().is_empty() {
+                        return Err(error(start, "empty phrase"));
+                    }
+                    Term::Phrase(text.to_lowercase())
+                }
+                '/' => {
+                    let (pattern, end) = read_delimited(&chars, pos, '/')
+                        .ok_or_else(|| error(pos, "unterminated regex, expected a closing '/'"))?;
+                    let regex = RegexBuilder::new(&pattern)
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| error(pos, &format!("invalid regex: {}", summary_line(&e.to_string()))))?;
+                    pos = end;
+                    Term::Regex(regex)
+                }
+                _ => {
+                    let word_start = pos;
+                    while pos < chars.len() && !chars[pos].is_whitespace() {
+                        pos += 1;
+                    }
+                    let word: String = chars[word_start..pos].iter().collect();
+                    parse_word(&word, word_start)?
+                }
+            };
+            clauses.push(Clause { negated, term });
+        }
+
+        Ok(Query { clauses })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    /// Whether any clause restricts results beyond the ranking text.
+    pub fn has_filters(&self) -> bool {
+        self.clauses
+            .iter()
+            .any(|clause| clause.negated || !matches!(clause.term, Term::Word(_)))
+    }
+
+    /// Evaluate every clause against the note.
+    pub fn matches(&self, note: &Note) -> bool {
+        self.clauses.iter().all(|clause| clause.matches(note))
+    }
+
+    /// Evaluate only the clauses that act as hard filters when ranking: all
+    /// of them except positive bare words, which instead feed the ranking
+    /// text from `free_text`.
+    pub fn matches_filters(&self, note: &Note) -> bool {
+        self.clauses
+            .iter()
+            .filter(|clause| clause.negated || !matches!(clause.term, Term::Word(_)))
+            .all(|clause| clause.matches(note))
+    }
+
+    /// The positive words and phrases, joined for use as a ranking query.
+    pub fn free_text(&self) -> String {
+        self.clauses
+            .iter()
+            .filter(|clause| !clause.negated)
+            .filter_map(|clause| match &clause.term {
+                Term::Word(word) => Some(word.as_str()),
+                Term::Phrase(phrase) => Some(phrase.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl Clause {
+    fn matches(&self, note: &Note) -> bool {
+        self.term.matches(note) != self.negated
+    }
+}
+
+impl Term {
+    fn matches(&self, note: &Note) -> bool {
+        match self {
+            Term::Word(word) => {
+                note.category.to_lowercase().contains(word) || note.text.to_lowercase().contains(word)
+            }
+            Term::Phrase(phrase) => note.text.to_lowercase().contains(phrase),
+            Term::Regex(regex) => regex.is_match(&note.text),
+            Term::Category(category) => note.category.eq_ignore_ascii_case(category),
+            Term::Tag(tag) => note.tags().contains(tag),
+            Term::AutoTag(tag) => note.auto_tags.contains(tag),
+            Term::Entity(name) => note.mentions(name),
+            Term::After(date) => note.parsed_date().is_some_and(|d| d >= *date),
+            Term::Before(date) => note.parsed_date().is_some_and(|d| d <= *date),
+        }
+    }
+}
+
+fn parse_word(word: &str, start: usize) -> Result<Term, ParseError> {
+    // Only known field names are special, so URLs and the like stay words
+    if let Some((field, value)) = word.split_once(':') {
+        let field = field.to_lowercase();
+        let value_start = start + field.chars().count() + 1;
+        let known = matches!(
+            field.as_str(),
+            "category" | "cat" | "tag" | "autotag" | "entity" | "after" | "since" | "before" | "until"
+        );
+        if known && value.is_empty() {
+            return Err(error(value_start, &format!("missing value after '{}:'", field)));
+        }
+        let date = |value: &str| {
+            parse_date(value).map_err(|e| error(value_start, &e))
+        };
+        match field.as_str() {
+            "category" | "cat" => return Ok(Term::Category(value.to_string())),
+            "tag" => return Ok(Term::Tag(value.trim_start_matches('#').to_lowercase())),
+            "autotag" => return Ok(Term::AutoTag(value.to_lowercase())),
+            // Multi-word names can be written with underscores: entity:ada_lovelace
+            "entity" => return Ok(Term::Entity(value.replace('_', " "))),
+            "after" | "since" => return Ok(Term::After(date(value)?)),
+            "before" | "until" => return Ok(Term::Before(date(value)?)),
+            _ => {}
+        }
+    }
+    if let Some(tag) = word.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+        return Ok(Term::Tag(tag.to_lowercase()));
+    }
+    Ok(Term::Word(word.to_lowercase()))
+}
+
+/// Read up to the closing `delimiter`, honouring `\` escapes of it.
+/// Returns the contents and the position just past the closing delimiter.
+fn read_delimited(chars: &[char], open: usize, delimiter: char) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut pos = open + 1;
+    while pos < chars.len() {
+        match chars[pos] {
+            '\\' if chars.get(pos + 1) == Some(&delimiter) => {
+                text.push(delimiter);
+                pos += 2;
+            }
+            c if c == delimiter => return Some((text, pos + 1)),
+            c => {
+                text.push(c);
+                pos += 1;
+            }
+        }
+    }
+    None
+}
+
+/// Regex errors span several lines; the last one holds the actual message.
+fn summary_line(message: &str) -> &str {
+    message.lines().last().unwrap_or(message).trim()
+}
+
+fn error(position: usize, message: &str) -> ParseError {
+    ParseError { position, message: message.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(line: &str) -> Note {
+        Note::parse(1, line)
+    }
+
+    fn parse_error(input: &str) -> ParseError {
+        Query::parse(input).expect_err(input)
+    }
+
+    #[test]
+    fn parse_reads_every_kind_of_clause() {
+        let query = Query::parse(r#"rust category:read #Async tag:web "exact Phrase" -draft /v\d+/ after:2026-01-02"#).unwrap();
+        assert_eq!(query.clauses.len(), 8);
+        assert_eq!(query.clauses.iter().filter(|clause| clause.negated).count(), 1);
+        assert!(matches!(&query.clauses[0].term, Term::Word(word) if word == "rust"));
+        assert!(matches!(&query.clauses[1].term, Term::Category(category) if category == "read"));
+        assert!(matches!(&query.clauses[2].term, Term::Tag(tag) if tag == "async"));
+        assert!(matches!(&query.clauses[3].term, Term::Tag(tag) if tag == "web"));
+        assert!(matches!(&query.clauses[4].term, Term::Phrase(phrase) if phrase == "exact phrase"));
+        assert!(query.clauses[5].negated && matches!(&query.clauses[5].term, Term::Word(word) if word == "draft"));
+        assert!(matches!(&query.clauses[6].term, Term::Regex(regex) if regex.as_str() == r"v\d+"));
+        assert!(matches!(query.clauses[7].term, Term::After(date) if date == NaiveDate::from_ymd_opt(2026, 1, 2).unwrap()));
+    }
+
+    #[test]
+    fn parse_keeps_unknown_fields_as_words() {
+        let query = Query::parse("https://example.com").unwrap();
+        assert!(matches!(&query.clauses[0].term, Term::Word(word) if word == "https://example.com"));
+    }
+
+    #[test]
+    fn parse_reports_error_positions() {
+        assert_eq!(parse_error("rust -").position, 5);
+        assert_eq!(parse_error(r#"a "open"#).position, 2);
+        assert_eq!(parse_error("a /open").position, 2);
+        assert_eq!(parse_error("a /(/").position, 2);
+        assert_eq!(parse_error("tag:").position, 4);
+        assert_eq!(parse_error(r#"x """#).position, 2);
+    }
+
+    #[test]
+    fn parse_rejects_bad_dates_without_panicking() {
+        for input in ["after:soon", "after:7é", "before:é", "since:99999999d", "until:99999999w", "after:99999999m"] {
+            assert_eq!(parse_error(input).position, input.find(':').unwrap() + 1, "{}", input);
+        }
+        assert!(Query::parse("after:7d before:2w since:3m").is_ok());
+    }
+
+    #[test]
+    fn matches_applies_every_clause() {
+        let query = Query::parse("rust -draft category:read").unwrap();
+        assert!(query.matches(&note("[2026-01-01] [read] rust book")));
+        assert!(!query.matches(&note("[2026-01-01] [read] rust draft")));
+        assert!(!query.matches(&note("[2026-01-01] [todo] rust book")));
+        assert!(!query.matches(&note("[2026-01-01] [read] go book")));
+    }
+
+    #[test]
+    fn matches_filters_ignores_positive_words() {
+        let query = Query::parse(r#"rust "async io" -draft category:read"#).unwrap();
+        assert!(query.matches_filters(&note("[2026-01-01] [read] async io in go")));
+        assert!(!query.matches_filters(&note("[2026-01-01] [read] go draft")));
+        assert!(!query.matches_filters(&note("[2026-01-01] [read] rust")));
+        assert!(!query.matches_filters(&note("[2026-01-01] [todo] rust async io")));
+    }
+
+    #[test]
+    fn free_text_joins_positive_words_and_phrases() {
+        let query = Query::parse(r#"Rust -draft "Async IO" tag:web /x/ tokio"#).unwrap();
+        assert_eq!(query.free_text(), "rust async io tokio");
+        assert_eq!(Query::parse("category:read -draft").unwrap().free_text(), "");
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+use std::path::Path;
+use crate::utils::embedder::Embedder;
+use crate::utils::semantic_search::{normalize_embedding, SearchError};
+use crate::utils::vector_store::{StoredEmbedding, VectorStore};
+
+const BATCH_SIZE: usize = 32;
+const BAR_WIDTH: usize = 30;
+
+/// Re-embed every entry of a store written by another model. New vectors go
+/// to a staging store a batch at a time, so an interrupted job picks up
+/// where it stopped; the staging store replaces the old one only once
+/// every entry is covered.
+pub fn reembed(
+    embedder: &dyn Embedder,
+    dir: &Path,
+    old_store: VectorStore,
+    old: Vec<StoredEmbedding>,
+) -> Result<(VectorStore, Vec<StoredEmbedding>), SearchError> {
+    if old.is_empty() {
+        // Nothing to convert; start over with the new model's id
+        old_store.discard()?;
+        return Ok(VectorStore::open(dir, embedder.model_id())?);
+    }
+
+    let (mut staging, mut done) = open_staging(embedder, dir, &old)?;
+    if !done.is_empty() {
+        eprintln!("Resuming re-embedding with {} ({} of {} done)", embedder.model_id(), done.len(), old.len());
+    }
+
+    let texts: Vec<&str> = old.iter().map(|entry| entry.text.as_str()).collect();
+    embed_batches(embedder, &texts[done.len()..], "Re-embedding notes", |text, embedding| {
+        staging.append(text, &embedding)?;
+        done.push(StoredEmbedding { text: text.to_string(), embedding });
+        Ok(())
+    })?;
+
+    let store = staging.promote()?;
+    Ok((store, done))
+}
+
+/// The staging store for this model, keeping what an earlier run finished
+/// if it is a prefix of `old`. Anything else (another model, or notes that
+/// changed in between) is thrown away.
+fn open_staging(
+    embedder: &dyn Embedder,
+    dir: &Path,
+    old: &[StoredEmbedding],
+) -> Result<(VectorStore, Vec<StoredEmbedding>), SearchError> {
+    let (staging, done) = VectorStore::open_staging(dir, embedder.model_id())?;
+    let resumable = staging.model_id() == embedder.model_id()
+        && done.len() <= old.len()
+        && done.iter().zip(old).all(|(new, old)| new.text == old.text);
+    if resumable {
+        return Ok((staging, done));
+    }
+    staging.discard()?;
+    Ok(VectorStore::open_staging(dir, embedder.model_id())?)
+}
+
+/// Encode `texts` in batches, handing each normalized embedding to `store`
+/// as soon as its batch is done, with a progress bar on stderr so it stays
+/// out of piped output. Returns how many were embedded.
+pub fn embed_batches(
+    embedder: &dyn Embedder,
+    texts: &[&str],
+    label: &str,
+    mut store: impl FnMut(&str, Vec<f32>) -> Result<(), SearchError>,
+) -> Result<usize, SearchError> {
+    let mut embedded = 0;
+    for batch in texts.chunks(BATCH_SIZE) {
+        print_progress(label, embedded, texts.len());
+        for (text, embedding) in batch.iter().zip(embedder.encode(batch)?) {
+            store(text, normalize_embedding(embedding))?;
+        }
+        embedded += batch.len();
+    }
+    if !texts.is_empty() {
+        print_progress(label, embedded, texts.len());
+        eprintln!();
+    }
+    Ok(embedded)
+}
+
+fn print_progress(label: &str, done: usize, total: usize) {
+    let filled = (done * BAR_WIDTH).checked_div(total).unwrap_or(BAR_WIDTH);
+    eprint!("\r{} [{}{}] {}/{}", label, "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), done, total);
+    let _ = io::stderr().flush();
+}
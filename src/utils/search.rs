@@ -0,0 +1,127 @@
+use clap::ValueEnum;
+use std::collections::HashMap;
+use crate::utils::lexical;
+use crate::utils::notes::{self, NoteFilter};
+use crate::utils::query::Query;
+use crate::utils::semantic_search::{SearchError, SearchResult, SemanticSearch};
+
+// Reciprocal rank fusion constant; 60 is the value from the original paper
+const RRF_K: f32 = 60.0;
+// How many candidates each ranker contributes before fusion
+const FUSION_POOL: usize = 50;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum SearchMode {
+    /// BM25 keyword ranking
+    Lexical,
+    /// Embedding similarity
+    Semantic,
+    /// Both, fused by reciprocal rank
+    #[default]
+    Hybrid,
+}
+
+impl SearchMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SearchMode::Lexical => "Lexical Search",
+            SearchMode::Semantic => "Semantic Search",
+            SearchMode::Hybrid => "Hybrid Search",
+        }
+    }
+
+    /// Next mode when cycling in the viewer.
+    pub fn next(&self) -> SearchMode {
+        match self {
+            SearchMode::Hybrid => SearchMode::Lexical,
+            SearchMode::Lexical => SearchMode::Semantic,
+            SearchMode::Semantic => SearchMode::Hybrid,
+        }
+    }
+}
+
+/// Search notes in the given mode. `query` uses the syntax from
+/// [`Query`]: positive words and phrases are ranked, everything else
+/// (fields, exclusions, regexes, phrases) restricts the candidates.
+///
+/// The score of each result depends on the mode: BM25 for lexical, cosine
+/// similarity for semantic and the fused reciprocal-rank score, scaled to
+/// 0..=1, for hybrid.
+/// A query made only of filters lists the matching notes, newest first,
+/// with a score of zero.
+///
+/// `semantic_search` may be `None` for lexical-only callers that want to skip
+/// loading the model.
+pub fn search_notes(
+    semantic_search: Option<&SemanticSearch>,
+    query: &str,
+    k: usize,
+    filter: &NoteFilter,
+    mode: SearchMode,
+) -> Result<Vec<SearchResult>, SearchError> {
+    let query = Query::parse(query)?;
+    let text = query.free_text();
+    let mut filter = filter.clone();
+    filter.query = query.clone();
+
+    if text.is_empty() {
+        let mut matching = notes::load_notes()?;
+        matching.retain(|note| filter.matches(note));
+        return Ok(matching
+            .into_iter()
+            .rev()
+            .take(k)
+            .map(|note| SearchResult { note, score: 0.0 })
+            .collect());
+    }
+
+    let semantic_search = match (mode, semantic_search) {
+        (SearchMode::Lexical, _) | (_, None) => return lexical_search(&query, &text, k, &filter),
+        (_, Some(semantic_search)) => semantic_search,
+    };
+    if mode == SearchMode::Semantic {
+        return semantic_search.search_filtered(&text, k, &filter);
+    }
+
+    let pool = k.max(FUSION_POOL);
+    let lexical = lexical_search(&query, &text, pool, &filter)?;
+    let semantic = semantic_search.search_filtered(&text, pool, &filter)?;
+    Ok(reciprocal_rank_fusion(&[lexical, semantic], k))
+}
+
+/// Every note containing all query words, ordered by BM25. Notes that only
+/// match by substring (e.g. a partly typed word) follow with a zero score.
+fn lexical_search(query: &Query, text: &str, k: usize, filter: &NoteFilter) -> Result<Vec<SearchResult>, SearchError> {
+    let mut candidates = notes::load_notes()?;
+    candidates.retain(|note| filter.matches(note) && query.matches(note));
+
+    let mut results = lexical::search(&candidates, text, candidates.len());
+    for note in candidates {
+        if !results.iter().any(|result| result.note.id == note.id) {
+            results.push(SearchResult { note, score: 0.0 });
+        }
+    }
+    results.truncate(k);
+    Ok(results)
+}
+
+/// Merge several rankings: each note scores `sum(1 / (RRF_K + rank))` over
+/// the rankings it appears in, divided by the best possible sum so a note
+/// ranked first everywhere scores 1.
+fn reciprocal_rank_fusion(rankings: &[Vec<SearchResult>], k: usize) -> Vec<SearchResult> {
+    let best = rankings.len() as f32 / (RRF_K + 1.0);
+    let mut fused: HashMap<usize, SearchResult> = HashMap::new();
+    for ranking in rankings {
+        for (rank, result) in ranking.iter().enumerate() {
+            let contribution = 1.0 / (RRF_K + rank as f32 + 1.0) / best;
+            fused
+                .entry(result.note.id)
+                .and_modify(|existing| existing.score += contribution)
+                .or_insert_with(|| SearchResult { note: result.note.clone(), score: contribution });
+        }
+    }
+    let mut results: Vec<SearchResult> = fused.into_values().collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.note.id.cmp(&b.note.id)));
+    results.truncate(k);
+    results
+}
@@ -1,61 +1,120 @@
-use rust_bert::pipelines::sentence_embeddings::{
-    SentenceEmbeddingsBuilder, SentenceEmbeddingsModelType,
-};
 use hnsw_rs::prelude::*;
 use hnsw_rs::dist::DistCosine;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use dirs::home_dir;
-use serde::{Serialize, Deserialize};
+use serde::Serialize;
 use std::fs;
 use std::io;
 use thiserror::Error;
+use crate::utils::config::{Config, IndexConfig};
+use crate::utils::embedder::{load_embedder, Embedder};
+use crate::utils::embedding_cache::{CacheStats, CachedEmbedder};
+use crate::utils::index::{self, IndexParams};
+use crate::utils::models::ModelError;
+use crate::utils::notes::{self, Note, NoteFilter};
+use crate::utils::quantize::{Quantization, QuantizedVectors};
+use crate::utils::query::ParseError;
+use crate::utils::reembed::{embed_batches, reembed};
+use crate::utils::vector_store::{self, StoredEmbedding, VectorStore};
 
-const EMBEDDINGS_FILE: &str = "embeddings.json";
-const EF_CONSTRUCTION: usize = 200;  // Higher values give better accuracy but slower construction
+// Embeddings were kept in this file before the binary store
+const LEGACY_EMBEDDINGS_FILE: &str = "embeddings.json";
+// ...and were always produced by this model
+const LEGACY_MODEL_ID: &str = "all-MiniLM-L6-v2";
+// Queries sampled from the stored embeddings when measuring recall
+const STATS_SAMPLE: usize = 100;
+const STATS_K: usize = 10;
+// Filtered searches over at most this many notes compare every one of them
+const EXACT_SCAN_LIMIT: usize = 2000;
 
 #[derive(Error, Debug)]
 pub enum SearchError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
-    #[error("Model error: {0}")]
-    Model(#[from] rust_bert::RustBertError),
+    #[error(transparent)]
+    Model(#[from] ModelError),
+    #[error("Invalid query: {0}")]
+    Query(#[from] ParseError),
+    #[error("Note {0} has no embedding")]
+    MissingEmbedding(usize),
 }
 
-#[derive(Serialize, Deserialize)]
-struct NoteEmbedding {
-    text: String,
-    embedding: Vec<f32>,
+/// A note returned by a search, with its cosine similarity to the query.
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub note: Note,
+    pub score: f32,
 }
 
 pub struct SemanticSearch {
-    model: rust_bert::pipelines::sentence_embeddings::SentenceEmbeddingsModel,
+    embedder: CachedEmbedder,
     index: std::cell::RefCell<Hnsw<f32, DistCosine>>,
-    notes: Vec<NoteEmbedding>,
+    params: IndexParams,
+    config: IndexConfig,
+    store: VectorStore,
+    notes: Vec<StoredEmbedding>,
+    quantized: Option<QuantizedVectors>,
+    /// Points were added since the index was last dumped
+    unsaved: bool,
+}
+
+/// What `SemanticSearch::reindex` changed.
+pub struct ReindexReport {
+    pub embedded: usize,
+    pub removed: usize,
+}
+
+/// Size and accuracy of the semantic index, for `pad index stats`.
+pub struct IndexStats {
+    pub embeddings: usize,
+    pub dimension: usize,
+    pub model_id: String,
+    pub store_bytes: u64,
+    pub dead_rows: usize,
+    pub params: IndexParams,
+    pub ef_search: usize,
+    pub quantization: Quantization,
+    pub rescore_factor: usize,
+    /// Number of stored embeddings used as test queries
+    pub sample: usize,
+    pub methods: Vec<MethodStats>,
+    /// `None` when the embedding cache is disabled
+    pub cache: Option<CacheStats>,
+}
+
+/// How one way of searching compares with an exact scan.
+pub struct MethodStats {
+    pub name: &'static str,
+    /// Memory taken by the vectors the method scans
+    pub vector_bytes: usize,
+    /// Fraction of the exact top `STATS_K` the method also returns
+    pub recall: f32,
 }
 
 impl SemanticSearch {
     pub fn new() -> Result<Self, SearchError> {
-        let model = SentenceEmbeddingsBuilder::remote(SentenceEmbeddingsModelType::AllMiniLmL6V2)
-            .create_model()
-            .map_err(SearchError::Model)?;
-
-        let index = Hnsw::<f32, DistCosine>::new(
-            16,   // max number of connections per layer
-            200,  // max number of elements
-            16,   // max layer
-            EF_CONSTRUCTION,  // ef construction
-            DistCosine{},
-        );
-
-        let notes = Self::load_embeddings()?;
-        
-        // Add existing embeddings to the index
-        let index = std::cell::RefCell::new(index);
-        for (i, note) in notes.iter().enumerate() {
-            index.borrow_mut().insert((&note.embedding, i));
-        }
+        let config = Config::load()?;
+        let embedder = CachedEmbedder::new(load_embedder(&config)?, &config.cache, &Self::get_data_path())?;
+        let config = config.index;
+        let (store, notes) = Self::load_embeddings(&embedder)?;
 
-        Ok(Self { model, index, notes })
+        // Reuse the dumped index unless the embeddings or the index settings
+        // changed since it was written
+        let checksum = embeddings_checksum(&notes);
+        let (index, params) = match index::load_index(&Self::get_data_path(), checksum, &config) {
+            Some(loaded) => loaded,
+            None => {
+                let params = IndexParams::new(&config, notes.len());
+                let index = build_index(&notes, params);
+                index::save_index(&Self::get_data_path(), &index, params, checksum)?;
+                (index, params)
+            }
+        };
+
+        let quantized = quantize(&config, &notes);
+        Ok(Self { embedder, index: std::cell::RefCell::new(index), params, config, store, notes, quantized, unsaved: false })
     }
 
     fn get_data_path() -> PathBuf {
@@ -65,100 +124,476 @@ impl SemanticSearch {
         path
     }
 
-    fn load_embeddings() -> Result<Vec<NoteEmbedding>, SearchError> {
-        let mut path = Self::get_data_path();
-        path.push(EMBEDDINGS_FILE);
-        
-        if !path.exists() {
-            return Ok(Vec::new());
+    /// Open the embedding store, first converting an `embeddings.json` left
+    /// by an older version. A store written by a different model than
+    /// `embedder` is re-embedded before use, since vectors from two models
+    /// can't be compared.
+    fn load_embeddings(embedder: &dyn Embedder) -> Result<(VectorStore, Vec<StoredEmbedding>), SearchError> {
+        let dir = Self::get_data_path();
+        let legacy_path = dir.join(LEGACY_EMBEDDINGS_FILE);
+        if legacy_path.exists() {
+            let count = vector_store::convert_json(&dir, &legacy_path, LEGACY_MODEL_ID)?;
+            eprintln!("Converted {} embeddings from {} to the binary store", count, LEGACY_EMBEDDINGS_FILE);
         }
 
-        let content = fs::read_to_string(path)?;
-        let mut notes: Vec<NoteEmbedding> = serde_json::from_str(&content)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let (mut store, mut notes) = VectorStore::open(&dir, embedder.model_id())?;
+        let stale = store.model_id() != embedder.model_id()
+            || store.dimension().is_some_and(|dimension| dimension != embedder.dimension());
+        if stale {
+            eprintln!("Embeddings were made with {}, switching to {}", store.model_id(), embedder.model_id());
+            (store, notes) = reembed(embedder, &dir, store, notes)?;
+        }
         for note in &mut notes {
             let embedding = std::mem::take(&mut note.embedding);
             note.embedding = normalize_embedding(embedding);
         }
-        Ok(notes)
+        Ok((store, notes))
     }
 
-    fn save_embeddings(&self) -> Result<(), SearchError> {
-        let mut path = Self::get_data_path();
-        path.push(EMBEDDINGS_FILE);
-        
-        let content = serde_json::to_string(&self.notes)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(path, content)?;
-        Ok(())
+    pub fn add_note(&mut self, text: &str) -> Result<(), SearchError> {
+        let embedding = self.embed(text)?;
+        self.add_embedding(text, embedding)
     }
 
-    pub fn add_note(&mut self, text: &str) -> Result<(), SearchError> {
-        let embedding = self.model.encode(&[text])
-            .map_err(SearchError::Model)?[0].to_vec();
-        let embedding = normalize_embedding(embedding);
-        
-        let note = NoteEmbedding {
+    /// Encode and normalize a single text.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>, SearchError> {
+        let embedding = self.embedder.encode(&[text])?.remove(0);
+        Ok(normalize_embedding(embedding))
+    }
+
+    /// Every stored embedding keyed by its text, for looking up many notes
+    /// without a scan each.
+    pub fn embeddings_by_text(&self) -> HashMap<&str, &[f32]> {
+        let mut embeddings = HashMap::new();
+        for note in &self.notes {
+            // A text stored twice keeps its first entry
+            embeddings.entry(note.text.as_str()).or_insert(note.embedding.as_slice());
+        }
+        embeddings
+    }
+
+    /// Store an embedding produced by `embed`, without encoding again.
+    pub fn add_embedding(&mut self, text: &str, embedding: Vec<f32>) -> Result<(), SearchError> {
+        // Appended to the store rather than rewriting it
+        self.store.append(text, &embedding)?;
+        let note = StoredEmbedding {
             text: text.to_string(),
             embedding: embedding.clone(),
         };
         
-        // Add to index
+        // Add to index, regrowing it once it's full
         let index = self.notes.len();
-        self.index.borrow_mut().insert((&embedding, index));
-        
-        // Add to notes
         self.notes.push(note);
-        
-        // Save embeddings
-        self.save_embeddings()?;
+        if self.notes.len() > self.params.capacity {
+            self.params = IndexParams::new(&self.config, self.notes.len());
+            self.index = std::cell::RefCell::new(build_index(&self.notes, self.params));
+        } else {
+            self.index.borrow_mut().insert((&embedding, index));
+        }
+        match &mut self.quantized {
+            Some(quantized) if index > 0 => quantized.push(&embedding),
+            // The dimension isn't known until the first embedding
+            _ => self.quantized = quantize(&self.config, &self.notes),
+        }
+        // Dumping the whole graph costs as much as the store append saved,
+        // so it happens once, when the search is dropped
+        self.unsaved = true;
         Ok(())
     }
 
-    pub fn search(&self, query: &str, k: usize) -> Result<Vec<(String, f32)>, SearchError> {
-        let query_embedding = self.model.encode(&[query])
-            .map_err(SearchError::Model)?[0].to_vec();
-        let query_embedding = normalize_embedding(query_embedding);
+    pub fn search(&self, query: &str, k: usize) -> Result<Vec<SearchResult>, SearchError> {
+        self.search_filtered(query, k, &NoteFilter::default())
+    }
+
+    /// Search only among notes accepted by `filter`. The filter is applied
+    /// during the search itself, so up to `k` matching notes come back
+    /// rather than whatever survives from an unfiltered top k.
+    pub fn search_filtered(&self, query: &str, k: usize, filter: &NoteFilter) -> Result<Vec<SearchResult>, SearchError> {
+        // Queries aren't cached: the viewer searches on every keystroke
+        let query_embedding = normalize_embedding(self.embedder.encode_uncached(&[query])?.remove(0));
+
+        let candidates: Vec<Note> = notes::load_notes()?
+            .into_iter()
+            .filter(|note| filter.matches(note))
+            .collect();
         
-        let neighbors = self.index.borrow().search(&query_embedding, k, EF_CONSTRUCTION);
+        let neighbors = if filter.is_empty() {
+            self.nearest(&query_embedding, k, None)
+        } else {
+            // Sorted ids of the embeddings belonging to a matching note
+            let texts: HashSet<&str> = candidates.iter().map(|note| note.text.as_str()).collect();
+            let allowed: Vec<usize> = self.notes
+                .iter()
+                .enumerate()
+                .filter(|(_, embedding)| texts.contains(embedding.text.as_str()))
+                .map(|(i, _)| i)
+                .collect();
+            if allowed.is_empty() {
+                return Ok(Vec::new());
+            }
+            self.nearest(&query_embedding, k, Some(&allowed))
+        };
         
-        let results: Vec<(String, f32)> = neighbors
+        let hits: Vec<(String, f32)> = neighbors
             .into_iter()
-            .map(|n| (self.notes[n.d_id].text.clone(), n.distance))
+            .map(|(id, score)| (self.notes[id].text.clone(), score))
             .collect();
             
+        Ok(attach_notes(hits, &candidates))
+    }
+
+    /// The existing note closest to `embedding`, if its cosine similarity is
+    /// at least `threshold`.
+    pub fn find_duplicate(&self, embedding: &[f32], threshold: f32) -> Result<Option<SearchResult>, SearchError> {
+        if self.notes.is_empty() {
+            return Ok(None);
+        }
+        // A few neighbours in case the closest embedding has no note left
+        let neighbors = self.nearest(embedding, 3, None);
+        let hits: Vec<(String, f32)> = neighbors
+            .into_iter()
+            .map(|(id, score)| (self.notes[id].text.clone(), score))
+            .filter(|(_, score)| *score >= threshold)
+            .collect();
+        Ok(attach_notes(hits, &notes::load_notes()?).into_iter().next())
+    }
+
+    /// Notes most similar to `note`, using its stored embedding rather than
+    /// encoding the text again. The note itself is never returned.
+    pub fn related(&self, note: &Note, k: usize) -> Result<Vec<SearchResult>, SearchError> {
+        let hits = self.similar_texts(&note.text, k)
+            .ok_or(SearchError::MissingEmbedding(note.id))?;
+
+        let mut candidates = notes::load_notes()?;
+        candidates.retain(|candidate| candidate.id != note.id);
+        let mut results = attach_notes(hits, &candidates);
+        results.truncate(k);
         Ok(results)
     }
 
+    /// Texts of the `k` embeddings nearest to the stored embedding of `text`,
+    /// with their similarity, excluding `text`'s own entry. `None` if `text`
+    /// has no embedding.
+    pub fn similar_texts(&self, text: &str, k: usize) -> Option<Vec<(String, f32)>> {
+        let own_index = self.notes.iter().position(|embedding| embedding.text == text)?;
+
+        // One extra neighbour, since the note finds itself first
+        let neighbors = self.nearest(&self.notes[own_index].embedding, k + 1, None);
+        let mut hits: Vec<(String, f32)> = neighbors
+            .into_iter()
+            .filter(|(id, _)| *id != own_index)
+            .map(|(id, score)| (self.notes[id].text.clone(), score))
+            .collect();
+        hits.truncate(k);
+        Some(hits)
+    }
+
+    /// Texts of the `k` embeddings nearest to `embedding`, with their
+    /// similarity.
+    pub fn similar_to(&self, embedding: &[f32], k: usize) -> Vec<(String, f32)> {
+        self.nearest(embedding, k, None)
+            .into_iter()
+            .map(|(id, score)| (self.notes[id].text.clone(), score))
+            .collect()
+    }
+
     pub fn remove_note_text(&mut self, text: &str) -> Result<(), SearchError> {
-        let original_len = self.notes.len();
-        self.notes.retain(|note| note.text != text);
-        if self.notes.len() == original_len {
+        self.remove_notes(&[text.to_string()])
+    }
+
+    /// Drop the embeddings for several notes at once, rebuilding the index a
+    /// single time.
+    pub fn remove_notes(&mut self, texts: &[String]) -> Result<(), SearchError> {
+        if self.store.remove(texts)? == 0 {
             return Ok(());
         }
+        self.notes.retain(|note| !texts.contains(&note.text));
+        if self.store.needs_compaction() {
+            self.store.compact(&self.notes)?;
+        }
+
+        // hnsw_rs can't delete points, so removal means a rebuild; it's
+        // dumped straight away so the next launch doesn't repeat it
+        self.rebuild_index()
+    }
+
+    /// Build the index again from the stored embeddings and dump it.
+    pub fn rebuild_index(&mut self) -> Result<(), SearchError> {
+        self.params = IndexParams::new(&self.config, self.notes.len());
+        self.index = std::cell::RefCell::new(build_index(&self.notes, self.params));
+        self.quantized = quantize(&self.config, &self.notes);
+        self.save_index()
+    }
+
+    /// Rewrite the embedding store from the embeddings in memory, which are
+    /// normalized, dropping deleted rows along the way.
+    pub fn compact_store(&mut self) -> Result<(), SearchError> {
+        self.store.compact(&self.notes)?;
+        Ok(())
+    }
+
+    /// Ids of embeddings the index doesn't return as their own nearest
+    /// neighbour. An identical embedding (a duplicate note) found first
+    /// counts as a match.
+    pub fn self_check(&self) -> Vec<usize> {
+        let index = self.index.borrow();
+        (0..self.notes.len())
+            .filter(|&id| {
+                let nearest = index.search(&self.notes[id].embedding, 1, self.ef_search(1));
+                !nearest.first().is_some_and(|n| n.d_id == id || n.distance <= 1e-6)
+            })
+            .collect()
+    }
+
+    /// Bring the stored embeddings in line with `notes`: embeddings whose
+    /// text no note has any more are dropped, and notes without one are
+    /// embedded in batches. With `full`, every note is embedded again from
+    /// scratch, bypassing the embedding cache. The index is rebuilt once at
+    /// the end.
+    pub fn reindex(&mut self, notes: &[Note], full: bool) -> Result<ReindexReport, SearchError> {
+        // Embeddings are keyed by text, so identical notes share one
+        let mut seen = HashSet::new();
+        let texts: Vec<&str> = notes
+            .iter()
+            .map(|note| note.text.as_str())
+            .filter(|text| seen.insert(*text))
+            .collect();
+        let orphans: Vec<String> = self.notes
+            .iter()
+            .filter(|embedding| !seen.contains(embedding.text.as_str()))
+            .map(|embedding| embedding.text.clone())
+            .collect();
+
+        let embedded = if full {
+            self.embedder.clear()?;
+            let mut fresh = Vec::new();
+            embed_batches(&self.embedder, &texts, "Embedding notes", |text, embedding| {
+                fresh.push(StoredEmbedding { text: text.to_string(), embedding });
+                Ok(())
+            })?;
+            self.store.compact(&fresh)?;
+            self.notes = fresh;
+            texts.len()
+        } else {
+            if !orphans.is_empty() {
+                self.store.remove(&orphans)?;
+                self.notes.retain(|embedding| !orphans.contains(&embedding.text));
+            }
+            let embedded: HashSet<&str> = self.notes.iter().map(|embedding| embedding.text.as_str()).collect();
+            let missing: Vec<&str> = texts.into_iter().filter(|text| !embedded.contains(text)).collect();
+            let (store, stored) = (&mut self.store, &mut self.notes);
+            embed_batches(&self.embedder, &missing, "Embedding missing notes", |text, embedding| {
+                store.append(text, &embedding)?;
+                stored.push(StoredEmbedding { text: text.to_string(), embedding });
+                Ok(())
+            })?;
+            if self.store.needs_compaction() {
+                self.store.compact(&self.notes)?;
+            }
+            missing.len()
+        };
+
+        self.rebuild_index()?;
+        Ok(ReindexReport { embedded, removed: orphans.len() })
+    }
+
+    /// Ids and similarities of the `k` stored embeddings nearest to `query`,
+    /// best first. With quantization on, the compressed vectors are scanned
+    /// for `rescore_factor * k` candidates, which are ranked by their
+    /// full-precision similarity; otherwise the HNSW graph is searched.
+    /// `allowed` must be sorted.
+    ///
+    /// hnsw_rs has no filtered search, so a small `allowed` set is scanned
+    /// exactly, and a larger one is searched by over-fetching from the graph
+    /// and dropping disallowed ids, widening until `k` are left.
+    fn nearest(&self, query: &[f32], k: usize, allowed: Option<&Vec<usize>>) -> Vec<(usize, f32)> {
+        match &self.quantized {
+            Some(quantized) => {
+                let candidates = quantized.nearest(query, k * self.config.rescore_factor.max(1), allowed.map(|ids| ids.as_slice()));
+                rescore(&self.notes, query, candidates, k)
+            }
+            None => {
+                let Some(allowed) = allowed else {
+                    return self.search_graph(query, k);
+                };
+                if allowed.len() <= EXACT_SCAN_LIMIT {
+                    return rescore(&self.notes, query, allowed.clone(), k);
+                }
+                // Expect allowed ids to turn up in proportion to their share
+                let total = self.notes.len();
+                let mut fetch = (k * total).div_ceil(allowed.len()) * 2;
+                loop {
+                    let neighbors = self.search_graph(query, fetch.min(total));
+                    let matching: Vec<(usize, f32)> = neighbors
+                        .into_iter()
+                        .filter(|(id, _)| allowed.binary_search(id).is_ok())
+                        .take(k)
+                        .collect();
+                    if matching.len() >= k || fetch >= total {
+                        return matching;
+                    }
+                    fetch *= 2;
+                }
+            }
+        }
+    }
+
+    fn search_graph(&self, query: &[f32], k: usize) -> Vec<(usize, f32)> {
+        self.index.borrow()
+            .search(query, k, self.ef_search(k))
+            .into_iter()
+            .map(|n| (n.d_id, 1.0 - n.distance))
+            .collect()
+    }
+
+    /// Measure the index against an exact scan: a sample of stored
+    /// embeddings are used as queries, and each method's top results are
+    /// compared with the true nearest neighbours. Both quantization modes
+    /// are measured whatever the config says, to help choose one.
+    pub fn stats(&self) -> IndexStats {
+        let dimension = self.notes.first().map_or(0, |note| note.embedding.len());
+        let vectors = || self.notes.iter().map(|note| note.embedding.as_slice());
+        let step = (self.notes.len() / STATS_SAMPLE).max(1);
+        let queries: Vec<&[f32]> = vectors().step_by(step).take(STATS_SAMPLE).collect();
+        let exact: Vec<Vec<usize>> = queries
+            .iter()
+            .map(|query| {
+                let all = (0..self.notes.len()).collect();
+                rescore(&self.notes, query, all, STATS_K).into_iter().map(|(id, _)| id).collect()
+            })
+            .collect();
+        let recall = |search: &dyn Fn(&[f32]) -> Vec<usize>| -> f32 {
+            if queries.is_empty() {
+                return 1.0;
+            }
+            let total: f32 = queries
+                .iter()
+                .zip(&exact)
+                .map(|(query, expected)| {
+                    let found = search(query);
+                    let hits = expected.iter().filter(|id| found.contains(id)).count();
+                    hits as f32 / expected.len().max(1) as f32
+                })
+                .sum();
+            total / queries.len() as f32
+        };
+
+        let mut methods = vec![MethodStats {
+            name: "hnsw",
+            vector_bytes: self.notes.len() * dimension * std::mem::size_of::<f32>(),
+            recall: recall(&|query| {
+                self.index.borrow()
+                    .search(query, STATS_K, self.ef_search(STATS_K))
+                    .into_iter()
+                    .map(|n| n.d_id)
+                    .collect()
+            }),
+        }];
+        for quantization in [Quantization::Int8, Quantization::Binary] {
+            let Some(quantized) = QuantizedVectors::new(quantization, dimension, vectors()) else {
+                continue;
+            };
+            let candidates = STATS_K * self.config.rescore_factor.max(1);
+            methods.push(MethodStats {
+                name: quantization.label(),
+                vector_bytes: quantized.size_bytes(),
+                recall: recall(&|query| {
+                    rescore(&self.notes, query, quantized.nearest(query, candidates, None), STATS_K)
+                        .into_iter()
+                        .map(|(id, _)| id)
+                        .collect()
+                }),
+            });
+        }
+
+        IndexStats {
+            embeddings: self.notes.len(),
+            dimension,
+            model_id: self.store.model_id().to_string(),
+            store_bytes: self.store.size_on_disk(),
+            dead_rows: self.store.dead_rows(),
+            params: self.params,
+            ef_search: self.config.ef_search,
+            quantization: self.config.quantization,
+            rescore_factor: self.config.rescore_factor,
+            sample: queries.len(),
+            methods,
+            cache: self.embedder.stats(),
+        }
+    }
+
+    /// The search candidate list must be at least as long as the number of
+    /// results wanted.
+    fn ef_search(&self, k: usize) -> usize {
+        self.config.ef_search.max(k)
+    }
 
-        self.rebuild_index();
-        self.save_embeddings()?;
+    fn save_index(&mut self) -> Result<(), SearchError> {
+        let checksum = embeddings_checksum(&self.notes);
+        index::save_index(&Self::get_data_path(), &self.index.borrow(), self.params, checksum)?;
+        self.unsaved = false;
         Ok(())
     }
+}
 
-    fn rebuild_index(&mut self) {
-        let max_elements = self.notes.len().max(200);
-        let mut index = Hnsw::<f32, DistCosine>::new(
-            16,
-            max_elements,
-            16,
-            EF_CONSTRUCTION,
-            DistCosine{},
-        );
-        for (i, note) in self.notes.iter().enumerate() {
-            index.insert((&note.embedding, i));
+impl Drop for SemanticSearch {
+    fn drop(&mut self) {
+        if self.unsaved {
+            // Not fatal: a missing or stale dump is rebuilt on the next run
+            if let Err(e) = self.save_index() {
+                eprintln!("Failed to save the search index: {}", e);
+            }
         }
-        self.index = std::cell::RefCell::new(index);
     }
 }
 
-fn normalize_embedding(mut embedding: Vec<f32>) -> Vec<f32> {
+fn build_index(notes: &[StoredEmbedding], params: IndexParams) -> Hnsw<f32, DistCosine> {
+    let index = params.build();
+    for (i, note) in notes.iter().enumerate() {
+        index.insert((&note.embedding, i));
+    }
+    index
+}
+
+fn quantize(config: &IndexConfig, notes: &[StoredEmbedding]) -> Option<QuantizedVectors> {
+    let dimension = notes.first()?.embedding.len();
+    QuantizedVectors::new(config.quantization, dimension, notes.iter().map(|note| note.embedding.as_slice()))
+}
+
+/// Rank `candidates` by their full-precision similarity to `query` and
+/// keep the best `k`.
+fn rescore(notes: &[StoredEmbedding], query: &[f32], candidates: Vec<usize>, k: usize) -> Vec<(usize, f32)> {
+    let mut scored: Vec<(usize, f32)> = candidates
+        .into_iter()
+        .map(|id| (id, notes[id].embedding.iter().zip(query).map(|(a, b)| a * b).sum()))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(k);
+    scored
+}
+
+fn embeddings_checksum(notes: &[StoredEmbedding]) -> u64 {
+    index::checksum(notes.iter().map(|note| (note.text.as_str(), note.embedding.as_slice())))
+}
+
+/// Join embedding hits back to the notes they came from. Embeddings are keyed
+/// by text, so each hit claims the first note with that text not already
+/// used; hits without a note (stale embeddings) are dropped.
+fn attach_notes(hits: Vec<(String, f32)>, all_notes: &[Note]) -> Vec<SearchResult> {
+    let mut used = vec![false; all_notes.len()];
+    hits.into_iter()
+        .filter_map(|(text, score)| {
+            let position = all_notes
+                .iter()
+                .enumerate()
+                .position(|(i, note)| !used[i] && note.text == text)?;
+            used[position] = true;
+            Some(SearchResult { note: all_notes[position].clone(), score })
+        })
+        .collect()
+}
+
+pub fn normalize_embedding(mut embedding: Vec<f32>) -> Vec<f32> {
     let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
     if norm > 0.0 {
         for value in &mut embedding {
@@ -167,3 +602,126 @@ fn normalize_embedding(mut embedding: Vec<f32>) -> Vec<f32> {
     }
     embedding
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::file_writing::write_to_file;
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // Each test points HOME at its own directory, so they take turns
+    static HOME_LOCK: Mutex<()> = Mutex::new(());
+    static NEXT_HOME: AtomicUsize = AtomicUsize::new(0);
+
+    /// Run `test` with an empty notes directory and the hashing embedder,
+    /// which needs no model files.
+    fn with_notes_dir(test: impl FnOnce()) {
+        let _lock = HOME_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let home = env::temp_dir().join(format!(
+            "pad-test-{}-{}",
+            std::process::id(),
+            NEXT_HOME.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(home.join("notes")).unwrap();
+        fs::write(home.join("notes").join("config.json"), r#"{ "embedding": { "backend": "hashing" } }"#).unwrap();
+        let old_home = env::var_os("HOME");
+        env::set_var("HOME", &home);
+
+        test();
+
+        match old_home {
+            Some(old_home) => env::set_var("HOME", old_home),
+            None => env::remove_var("HOME"),
+        }
+        let _ = fs::remove_dir_all(&home);
+    }
+
+    fn add(search: &mut SemanticSearch, text: &str) {
+        write_to_file(Some("general"), text).unwrap();
+        search.add_note(text).unwrap();
+    }
+
+    fn top_texts(search: &SemanticSearch, query: &str, k: usize) -> Vec<String> {
+        search.search(query, k).unwrap().into_iter().map(|result| result.note.text).collect()
+    }
+
+    const NOTES: [&str; 4] = [
+        "rust borrow checker and lifetimes",
+        "banana bread recipe with walnuts",
+        "weekly team meeting agenda",
+        "kubernetes pod autoscaling",
+    ];
+
+    #[test]
+    fn search_finds_added_notes() {
+        with_notes_dir(|| {
+            let mut search = SemanticSearch::new().unwrap();
+            for text in NOTES {
+                add(&mut search, text);
+            }
+            assert_eq!(top_texts(&search, "borrow checker", 1), [NOTES[0]]);
+            assert_eq!(top_texts(&search, "banana walnuts", 1), [NOTES[1]]);
+            assert_eq!(top_texts(&search, "autoscaling kubernetes pods", 1), [NOTES[3]]);
+        });
+    }
+
+    #[test]
+    fn added_notes_survive_a_restart() {
+        with_notes_dir(|| {
+            let mut search = SemanticSearch::new().unwrap();
+            for text in NOTES {
+                add(&mut search, text);
+            }
+            drop(search);
+
+            let search = SemanticSearch::new().unwrap();
+            assert_eq!(search.stats().embeddings, NOTES.len());
+            assert_eq!(top_texts(&search, "team meeting", 1), [NOTES[2]]);
+            assert!(search.self_check().is_empty());
+        });
+    }
+
+    #[test]
+    fn removed_notes_are_not_found() {
+        with_notes_dir(|| {
+            let mut search = SemanticSearch::new().unwrap();
+            for text in NOTES {
+                add(&mut search, text);
+            }
+            search.remove_note_text(NOTES[1]).unwrap();
+            assert!(!top_texts(&search, "banana bread", NOTES.len()).contains(&NOTES[1].to_string()));
+            drop(search);
+
+            let search = SemanticSearch::new().unwrap();
+            assert_eq!(search.stats().embeddings, NOTES.len() - 1);
+            assert!(!top_texts(&search, "banana bread", NOTES.len()).contains(&NOTES[1].to_string()));
+        });
+    }
+
+    #[test]
+    fn reindex_embeds_missing_notes_and_drops_orphans() {
+        with_notes_dir(|| {
+            // Notes written without embeddings, as if notes.txt was edited by hand
+            for text in NOTES {
+                write_to_file(Some("general"), text).unwrap();
+            }
+            let mut search = SemanticSearch::new().unwrap();
+            let report = search.reindex(&notes::load_notes().unwrap(), false).unwrap();
+            assert_eq!((report.embedded, report.removed), (NOTES.len(), 0));
+            assert_eq!(top_texts(&search, "borrow checker", 1), [NOTES[0]]);
+
+            let mut remaining = notes::load_notes().unwrap();
+            remaining.retain(|note| note.text != NOTES[0]);
+            notes::save_notes(&remaining).unwrap();
+            let report = search.reindex(&remaining, false).unwrap();
+            assert_eq!((report.embedded, report.removed), (0, 1));
+            assert!(!top_texts(&search, "borrow checker", NOTES.len()).contains(&NOTES[0].to_string()));
+
+            let report = search.reindex(&remaining, true).unwrap();
+            assert_eq!((report.embedded, report.removed), (remaining.len(), 0));
+            assert_eq!(top_texts(&search, "banana walnuts", 1), [NOTES[1]]);
+        });
+    }
+}
@@ -0,0 +1,207 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use crate::utils::lexical::tokenize;
+use crate::utils::notes::Note;
+use crate::utils::semantic_search::SemanticSearch;
+
+const MAX_ITERATIONS: usize = 50;
+const MAX_TOPICS: usize = 12;
+const LABEL_TERMS: usize = 3;
+// Notes compared pairwise when scoring a choice of k
+const SILHOUETTE_SAMPLE: usize = 500;
+
+// Words too common to describe a topic
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "how",
+    "i", "in", "is", "it", "its", "my", "of", "on", "or", "that", "the", "this", "to", "was",
+    "what", "when", "where", "which", "who", "why", "will", "with", "you", "your", "about",
+    "into", "out", "up", "can", "should", "could", "would", "some", "more", "new", "get", "use",
+    "http", "https", "www", "com",
+];
+
+/// A group of notes about the same thing, labelled with its most
+/// representative terms.
+#[derive(Clone, Debug)]
+pub struct Topic {
+    pub label: String,
+    pub notes: Vec<Note>,
+}
+
+/// Cluster notes by their embeddings with spherical k-means. With `k` unset,
+/// every k from 2 up to a limit based on the number of notes is tried and
+/// the one with the best silhouette score is kept. Notes without an
+/// embedding are left out. Topics are returned largest first.
+pub fn find_topics(semantic_search: &SemanticSearch, all_notes: &[Note], k: Option<usize>) -> Vec<Topic> {
+    let embeddings = semantic_search.embeddings_by_text();
+    let (notes, vectors): (Vec<&Note>, Vec<&[f32]>) = all_notes
+        .iter()
+        .filter_map(|note| Some((note, *embeddings.get(note.text.as_str())?)))
+        .unzip();
+    if notes.is_empty() {
+        return Vec::new();
+    }
+
+    let assignments = match k {
+        Some(k) => kmeans(&vectors, k.clamp(1, notes.len())),
+        None if notes.len() < 4 => vec![0; notes.len()],
+        None => {
+            let max_k = ((notes.len() as f32).sqrt().ceil() as usize + 1).min(MAX_TOPICS).min(notes.len() - 1);
+            (2..=max_k.max(2))
+                .map(|k| {
+                    let assignments = kmeans(&vectors, k);
+                    (silhouette(&vectors, &assignments), assignments)
+                })
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, assignments)| assignments)
+                .unwrap_or_else(|| vec![0; notes.len()])
+        }
+    };
+
+    let topic_count = assignments.iter().max().map_or(0, |max| max + 1);
+    let mut groups: Vec<Vec<Note>> = vec![Vec::new(); topic_count];
+    for (note, topic) in notes.iter().zip(&assignments) {
+        groups[*topic].push((*note).clone());
+    }
+    groups.retain(|group| !group.is_empty());
+
+    let labels = label_groups(&groups);
+    let mut topics: Vec<Topic> = groups
+        .into_iter()
+        .zip(labels)
+        .map(|(notes, label)| Topic { label, notes })
+        .collect();
+    topics.sort_by_key(|topic| Reverse(topic.notes.len()));
+    topics
+}
+
+/// Spherical k-means: vectors are unit length, so similarity is the dot
+/// product and centroids are renormalized means. Seeds are chosen by
+/// farthest-first traversal from the first vector, which keeps the result
+/// deterministic.
+fn kmeans(vectors: &[&[f32]], k: usize) -> Vec<usize> {
+    let mut centroids: Vec<Vec<f32>> = vec![vectors[0].to_vec()];
+    while centroids.len() < k {
+        let farthest = (0..vectors.len())
+            .max_by(|&a, &b| {
+                let best = |i: usize| centroids.iter().map(|c| dot(vectors[i], c)).fold(f32::MIN, f32::max);
+                best(b).total_cmp(&best(a))
+            })
+            .expect("vectors is not empty");
+        centroids.push(vectors[farthest].to_vec());
+    }
+
+    let mut assignments = vec![0; vectors.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (i, vector) in vectors.iter().enumerate() {
+            let best = (0..k)
+                .max_by(|&a, &b| dot(vector, &centroids[a]).total_cmp(&dot(vector, &centroids[b])))
+                .unwrap_or(0);
+            if assignments[i] != best {
+                assignments[i] = best;
+                changed = true;
+            }
+        }
+
+        for (c, centroid) in centroids.iter_mut().enumerate() {
+            let mut sum = vec![0.0; centroid.len()];
+            for (vector, _) in vectors.iter().zip(&assignments).filter(|(_, a)| **a == c) {
+                for (total, value) in sum.iter_mut().zip(vector.iter()) {
+                    *total += value;
+                }
+            }
+            let norm = sum.iter().map(|v| v * v).sum::<f32>().sqrt();
+            // An empty cluster keeps its previous centroid
+            if norm > 0.0 {
+                *centroid = sum.into_iter().map(|v| v / norm).collect();
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+    assignments
+}
+
+/// Mean silhouette coefficient using cosine distance; higher is better.
+/// It is quadratic in the number of notes, so large pads are scored on an
+/// evenly spaced sample of at most `SILHOUETTE_SAMPLE` notes.
+fn silhouette(vectors: &[&[f32]], assignments: &[usize]) -> f32 {
+    let k = assignments.iter().max().map_or(0, |max| max + 1);
+    let step = vectors.len().div_ceil(SILHOUETTE_SAMPLE).max(1);
+    let sample: Vec<usize> = (0..vectors.len()).step_by(step).collect();
+    let total: f32 = sample
+        .iter()
+        .map(|&i| {
+            let mut sums = vec![0.0f32; k];
+            let mut counts = vec![0usize; k];
+            for &j in &sample {
+                if i != j {
+                    sums[assignments[j]] += 1.0 - dot(vectors[i], vectors[j]);
+                    counts[assignments[j]] += 1;
+                }
+            }
+            let own = assignments[i];
+            if counts[own] == 0 {
+                return 0.0;
+            }
+            let a = sums[own] / counts[own] as f32;
+            let b = (0..k)
+                .filter(|&c| c != own && counts[c] > 0)
+                .map(|c| sums[c] / counts[c] as f32)
+                .fold(f32::MAX, f32::min);
+            if b == f32::MAX {
+                0.0
+            } else {
+                (b - a) / a.max(b)
+            }
+        })
+        .sum();
+    total / sample.len() as f32
+}
+
+/// Label each group with the terms that are frequent in it but rare in the
+/// other groups (TF-IDF, treating each group as one document).
+fn label_groups(groups: &[Vec<Note>]) -> Vec<String> {
+    let stopwords: HashSet<&str> = STOPWORDS.iter().copied().collect();
+    let term_counts: Vec<HashMap<String, usize>> = groups
+        .iter()
+        .map(|group| {
+            let mut counts = HashMap::new();
+            for note in group {
+                for token in tokenize(&note.text) {
+                    if token.len() > 2 && !stopwords.contains(token.as_str()) && !token.chars().all(|c| c.is_numeric()) {
+                        *counts.entry(token).or_insert(0) += 1;
+                    }
+                }
+            }
+            counts
+        })
+        .collect();
+
+    let group_count = groups.len() as f32;
+    term_counts
+        .iter()
+        .map(|counts| {
+            let mut scored: Vec<(&String, f32)> = counts
+                .iter()
+                .map(|(term, count)| {
+                    let groups_with_term = term_counts.iter().filter(|c| c.contains_key(term)).count() as f32;
+                    (term, *count as f32 * (1.0 + group_count / groups_with_term).ln())
+                })
+                .collect();
+            scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+            let terms: Vec<&str> = scored.iter().take(LABEL_TERMS).map(|(term, _)| term.as_str()).collect();
+            if terms.is_empty() {
+                "misc".to_string()
+            } else {
+                terms.join(", ")
+            }
+        })
+        .collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
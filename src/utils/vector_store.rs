@@ -0,0 +1,521 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const STORE_NAME: &str = "embeddings";
+// Where a re-embedding job writes until it has covered every note
+const STAGING_NAME: &str = "embeddings.next";
+// Present while `promote` renames the staging files over the main ones
+const PROMOTING_MARKER: &str = "embeddings.promoting";
+const MAGIC: &[u8; 8] = b"PADVEC01";
+// Rows start at a fixed offset so the file can be memory-mapped as
+// `[f32; dimension]` rows
+const HEADER_LEN: usize = 128;
+// The compaction generation takes the last eight bytes of the header
+const GENERATION_OFFSET: usize = HEADER_LEN - 8;
+const MAX_MODEL_ID_LEN: usize = GENERATION_OFFSET - 16;
+// Compact once dead rows make up this fraction of the file
+const COMPACT_RATIO: usize = 4;
+const MIN_DEAD_ROWS: usize = 32;
+
+/// One line of the text table. Rows are never rewritten in place: a row
+/// gets a line with its text when it is appended and a line without one
+/// when it is deleted.
+#[derive(Serialize, Deserialize)]
+struct TableEntry {
+    row: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+/// First line of a table written by a compaction. It carries the same
+/// generation as the vector file's header, so a vector file and table left
+/// from different compactions are told apart.
+#[derive(Serialize, Deserialize)]
+struct TableHeader {
+    generation: u64,
+}
+
+/// The two files making up a store named `name`: `<name>.bin` and
+/// `<name>.jsonl`.
+#[derive(Clone)]
+struct StorePaths {
+    vectors: PathBuf,
+    table: PathBuf,
+}
+
+impl StorePaths {
+    fn new(dir: &Path, name: &str) -> Self {
+        Self {
+            vectors: dir.join(format!("{}.bin", name)),
+            table: dir.join(format!("{}.jsonl", name)),
+        }
+    }
+
+    fn with_suffix(&self, suffix: &str) -> Self {
+        let add = |path: &Path| PathBuf::from(format!("{}{}", path.display(), suffix));
+        Self { vectors: add(&self.vectors), table: add(&self.table) }
+    }
+}
+
+/// A stored embedding and the note text it belongs to.
+pub struct StoredEmbedding {
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Embeddings on disk as two files in the notes directory:
+///
+/// - `embeddings.bin`: a 128-byte header (magic, dimension, model id,
+///   compaction generation) followed by contiguous little-endian f32 rows
+/// - `embeddings.jsonl`: which note text each row belongs to, and which
+///   rows have been deleted
+///
+/// Adding an embedding appends to both files; deleted rows stay in the
+/// vector file until enough of them pile up to be worth a compaction.
+pub struct VectorStore {
+    paths: StorePaths,
+    model_id: String,
+    dimension: Option<usize>,
+    /// Bumped by every compaction, see `TableHeader`
+    generation: u64,
+    /// Row number of each live entry, in the order `load` returned them
+    rows: Vec<usize>,
+    texts: Vec<String>,
+    total_rows: usize,
+}
+
+impl VectorStore {
+    /// Open the store in `dir`, returning it with its live embeddings in
+    /// insertion order. A store that doesn't exist yet is created on the
+    /// first append, tagged with `model_id`.
+    pub fn open(dir: &Path, model_id: &str) -> io::Result<(VectorStore, Vec<StoredEmbedding>)> {
+        let main = StorePaths::new(dir, STORE_NAME);
+        let staging = StorePaths::new(dir, STAGING_NAME);
+        // Finish a `promote` interrupted between its two renames. Without
+        // the marker a lone staging table is just left over from `discard`.
+        let promoting = dir.join(PROMOTING_MARKER);
+        if promoting.exists() {
+            if staging.table.exists() && !staging.vectors.exists() {
+                fs::rename(&staging.table, &main.table)?;
+            }
+            fs::remove_file(&promoting)?;
+        }
+        Self::open_paths(main, model_id)
+    }
+
+    /// Open the staging store a re-embedding job writes to.
+    pub fn open_staging(dir: &Path, model_id: &str) -> io::Result<(VectorStore, Vec<StoredEmbedding>)> {
+        Self::open_paths(StorePaths::new(dir, STAGING_NAME), model_id)
+    }
+
+    fn open_paths(paths: StorePaths, model_id: &str) -> io::Result<(VectorStore, Vec<StoredEmbedding>)> {
+        let mut store = VectorStore {
+            paths,
+            model_id: model_id.to_string(),
+            dimension: None,
+            generation: 0,
+            rows: Vec::new(),
+            texts: Vec::new(),
+            total_rows: 0,
+        };
+        if !store.paths.vectors.exists() {
+            return Ok((store, Vec::new()));
+        }
+
+        let bytes = fs::read(&store.paths.vectors)?;
+        let (dimension, stored_model_id, generation) = parse_header(&bytes)?;
+        let Some(table) = read_table(&store.paths, generation)? else {
+            eprintln!(
+                "The embedding store was interrupted while being compacted and is dropped; \
+                 run `pad reindex` to embed the notes again"
+            );
+            for path in [&store.paths.table, &store.paths.vectors] {
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+            return Ok((store, Vec::new()));
+        };
+        store.generation = generation;
+        // A store compacted down to nothing has no dimension yet
+        store.dimension = (dimension > 0).then_some(dimension);
+        store.model_id = stored_model_id;
+        let row_len = dimension * 4;
+        store.total_rows = (bytes.len() - HEADER_LEN).checked_div(row_len).unwrap_or(0);
+        // A torn append leaves a partial row at the end. It's cut off, or
+        // the next append would land after it and every later row would be
+        // read from the wrong offset.
+        let complete_len = HEADER_LEN + store.total_rows * row_len;
+        if bytes.len() > complete_len {
+            OpenOptions::new().write(true).open(&store.paths.vectors)?.set_len(complete_len as u64)?;
+        }
+
+        let mut texts: Vec<Option<String>> = vec![None; store.total_rows];
+        // Likewise a line cut short by an interrupted write, which the next
+        // line would otherwise be appended to
+        let complete_len = table.iter().rposition(|byte| *byte == b'\n').map_or(0, |i| i + 1);
+        if table.len() > complete_len {
+            OpenOptions::new().write(true).open(&store.paths.table)?.set_len(complete_len as u64)?;
+        }
+        // The header line doesn't parse as an entry and is skipped
+        for line in table[..complete_len].split(|byte| *byte == b'\n') {
+            let Ok(entry) = serde_json::from_slice::<TableEntry>(line) else {
+                continue;
+            };
+            if entry.row < texts.len() {
+                texts[entry.row] = entry.text;
+            }
+        }
+
+        let mut embeddings = Vec::new();
+        for (row, text) in texts.into_iter().enumerate() {
+            let Some(text) = text else { continue };
+            let start = HEADER_LEN + row * row_len;
+            let embedding = bytes[start..start + row_len]
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect();
+            store.rows.push(row);
+            store.texts.push(text.clone());
+            embeddings.push(StoredEmbedding { text, embedding });
+        }
+        Ok((store, embeddings))
+    }
+
+    /// The model the stored embeddings were produced with.
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// Dimension of the stored vectors; `None` while the store is empty.
+    pub fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    /// Rows still taking up space in the vector file after being deleted.
+    pub fn dead_rows(&self) -> usize {
+        self.total_rows - self.rows.len()
+    }
+
+    /// Size of the vector and text files in bytes.
+    pub fn size_on_disk(&self) -> u64 {
+        [&self.paths.vectors, &self.paths.table]
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .sum()
+    }
+
+    /// Append an embedding to the end of the store.
+    pub fn append(&mut self, text: &str, embedding: &[f32]) -> io::Result<()> {
+        match self.dimension {
+            Some(dimension) if dimension != embedding.len() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Embedding has {} dimensions, the store has {}", embedding.len(), dimension),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                write_header(&self.paths.vectors, embedding.len(), &self.model_id, self.generation)?;
+                self.dimension = Some(embedding.len());
+            }
+        }
+
+        let mut vectors = OpenOptions::new().append(true).open(&self.paths.vectors)?;
+        vectors.write_all(&row_bytes(embedding))?;
+
+        let row = self.total_rows;
+        self.append_table(&[TableEntry { row, text: Some(text.to_string()) }])?;
+        self.rows.push(row);
+        self.texts.push(text.to_string());
+        self.total_rows += 1;
+        Ok(())
+    }
+
+    /// Delete every entry whose text is in `texts`, returning how many were
+    /// removed. Live entries keep their relative order.
+    pub fn remove(&mut self, texts: &[String]) -> io::Result<usize> {
+        let deleted: Vec<TableEntry> = self.rows
+            .iter()
+            .zip(&self.texts)
+            .filter(|(_, text)| texts.contains(text))
+            .map(|(row, _)| TableEntry { row: *row, text: None })
+            .collect();
+        if deleted.is_empty() {
+            return Ok(0);
+        }
+        self.append_table(&deleted)?;
+
+        (self.rows, self.texts) = self.rows
+            .iter()
+            .copied()
+            .zip(self.texts.drain(..))
+            .filter(|(_, text)| !texts.contains(text))
+            .unzip();
+        Ok(deleted.len())
+    }
+
+    /// Whether enough rows are dead that `compact` is worth running.
+    pub fn needs_compaction(&self) -> bool {
+        let dead = self.dead_rows();
+        dead >= MIN_DEAD_ROWS && dead * COMPACT_RATIO >= self.total_rows
+    }
+
+    /// Rewrite both files with only the live entries, in order. `embeddings`
+    /// must be the live embeddings as held in memory. The new files are
+    /// written beside the old ones and renamed over them.
+    pub fn compact(&mut self, embeddings: &[StoredEmbedding]) -> io::Result<()> {
+        let (rows, texts) = write_store(&self.paths, &self.model_id, self.generation + 1, embeddings)?;
+        self.generation += 1;
+        self.total_rows = rows.len();
+        self.rows = rows;
+        self.texts = texts;
+        self.dimension = embeddings.first().map(|e| e.embedding.len());
+        Ok(())
+    }
+
+    /// Delete the store's files, e.g. a staging store for a model that is
+    /// no longer selected.
+    pub fn discard(self) -> io::Result<()> {
+        for path in [&self.paths.table, &self.paths.vectors] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move a finished staging store over the main store in the same
+    /// directory, replacing it.
+    pub fn promote(mut self) -> io::Result<VectorStore> {
+        let dir = self.paths.vectors.parent().map(Path::to_path_buf).unwrap_or_default();
+        let main = StorePaths::new(&dir, STORE_NAME);
+        // If this stops between the renames, `open` sees the marker and
+        // moves the table over
+        let promoting = dir.join(PROMOTING_MARKER);
+        fs::write(&promoting, "")?;
+        fs::rename(&self.paths.vectors, &main.vectors)?;
+        fs::rename(&self.paths.table, &main.table)?;
+        fs::remove_file(&promoting)?;
+        self.paths = main;
+        Ok(self)
+    }
+
+    fn append_table(&self, entries: &[TableEntry]) -> io::Result<()> {
+        let mut table = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.paths.table)?;
+        let mut content = String::new();
+        for entry in entries {
+            let line = serde_json::to_string(entry)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            content.push_str(&line);
+            content.push('\n');
+        }
+        table.write_all(content.as_bytes())
+    }
+}
+
+/// The table of the store at `paths` if it belongs with a vector file of
+/// `generation`; a missing table reads as empty. A compaction interrupted
+/// between its renames leaves the matching table beside the old one, and
+/// it is moved into place. `None` when no matching table exists.
+fn read_table(paths: &StorePaths, generation: u64) -> io::Result<Option<Vec<u8>>> {
+    let table = match fs::read(&paths.table) {
+        Ok(table) => table,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    if table_generation(&table) == generation {
+        return Ok(Some(table));
+    }
+    let table_tmp = paths.with_suffix(".tmp").table;
+    match fs::read(&table_tmp) {
+        Ok(table) if table_generation(&table) == generation => {
+            fs::rename(&table_tmp, &paths.table)?;
+            Ok(Some(table))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Generation from a table's header line; tables from before compactions
+/// were numbered have none and count as 0.
+fn table_generation(table: &[u8]) -> u64 {
+    let first_line = table.split(|byte| *byte == b'\n').next().unwrap_or_default();
+    serde_json::from_slice::<TableHeader>(first_line).map_or(0, |header| header.generation)
+}
+
+/// Write a fresh store at `paths`, replacing any existing one, and return
+/// the row and text of each entry.
+fn write_store(
+    paths: &StorePaths,
+    model_id: &str,
+    generation: u64,
+    embeddings: &[StoredEmbedding],
+) -> io::Result<(Vec<usize>, Vec<String>)> {
+    let tmp = paths.with_suffix(".tmp");
+    let (vectors_tmp, table_tmp) = (tmp.vectors, tmp.table);
+
+    let dimension = embeddings.first().map_or(0, |e| e.embedding.len());
+    write_header(&vectors_tmp, dimension, model_id, generation)?;
+    let mut vectors = OpenOptions::new().append(true).open(&vectors_tmp)?;
+    let mut table = serde_json::to_string(&TableHeader { generation })
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    table.push('\n');
+    for (row, entry) in embeddings.iter().enumerate() {
+        if entry.embedding.len() != dimension {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Embeddings have different dimensions"));
+        }
+        vectors.write_all(&row_bytes(&entry.embedding))?;
+        let line = serde_json::to_string(&TableEntry { row, text: Some(entry.text.clone()) })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        table.push_str(&line);
+        table.push('\n');
+    }
+    vectors.sync_all()?;
+    fs::write(&table_tmp, table)?;
+
+    // A crash between the renames leaves vectors and table of different
+    // generations, which `open` detects
+    fs::rename(&vectors_tmp, &paths.vectors)?;
+    fs::rename(&table_tmp, &paths.table)?;
+    Ok((
+        (0..embeddings.len()).collect(),
+        embeddings.iter().map(|e| e.text.clone()).collect(),
+    ))
+}
+
+/// Convert an `embeddings.json` file from older versions into a binary
+/// store in `dir`. The JSON file is kept as `embeddings.json.bak`.
+pub fn convert_json(dir: &Path, json_path: &Path, model_id: &str) -> io::Result<usize> {
+    #[derive(Deserialize)]
+    struct JsonEmbedding {
+        text: String,
+        embedding: Vec<f32>,
+    }
+
+    let content = fs::read_to_string(json_path)?;
+    let entries: Vec<JsonEmbedding> = serde_json::from_str(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let embeddings: Vec<StoredEmbedding> = entries
+        .into_iter()
+        .map(|entry| StoredEmbedding { text: entry.text, embedding: entry.embedding })
+        .collect();
+    write_store(&StorePaths::new(dir, STORE_NAME), model_id, 1, &embeddings)?;
+    fs::rename(json_path, json_path.with_extension("json.bak"))?;
+    Ok(embeddings.len())
+}
+
+fn write_header(path: &Path, dimension: usize, model_id: &str, generation: u64) -> io::Result<()> {
+    let model_id = model_id.as_bytes();
+    if model_id.len() > MAX_MODEL_ID_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Model id too long for the store header"));
+    }
+    let mut header = vec![0u8; HEADER_LEN];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&(dimension as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&(model_id.len() as u32).to_le_bytes());
+    header[16..16 + model_id.len()].copy_from_slice(model_id);
+    header[GENERATION_OFFSET..].copy_from_slice(&generation.to_le_bytes());
+    fs::write(path, header)
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<(usize, String, u64)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("embedding store: {}", message));
+    if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+        return Err(invalid("not a pad embedding store"));
+    }
+    let dimension = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+    let id_len = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) as usize;
+    if id_len > MAX_MODEL_ID_LEN {
+        return Err(invalid("corrupt header"));
+    }
+    let model_id = String::from_utf8(bytes[16..16 + id_len].to_vec()).map_err(|_| invalid("corrupt header"))?;
+    let generation = u64::from_le_bytes(bytes[GENERATION_OFFSET..HEADER_LEN].try_into().unwrap());
+    Ok((dimension, model_id, generation))
+}
+
+fn row_bytes(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "pad-store-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn embedding(text: &str, value: f32) -> StoredEmbedding {
+        StoredEmbedding { text: text.to_string(), embedding: vec![value, 1.0] }
+    }
+
+    fn texts(embeddings: &[StoredEmbedding]) -> Vec<&str> {
+        embeddings.iter().map(|e| e.text.as_str()).collect()
+    }
+
+    /// A store with rows a, b, c where b is deleted, then compacted with
+    /// the new vector file in place but the old table still beside it.
+    fn interrupted_compaction(dir: &Path) -> StorePaths {
+        let (mut store, _) = VectorStore::open(dir, "test").unwrap();
+        for (text, value) in [("a", 0.0), ("b", 1.0), ("c", 2.0)] {
+            store.append(text, &embedding(text, value).embedding).unwrap();
+        }
+        store.remove(&["b".to_string()]).unwrap();
+        let paths = StorePaths::new(dir, STORE_NAME);
+        let tmp = paths.with_suffix(".tmp");
+        write_store(&tmp, "test", 1, &[embedding("a", 0.0), embedding("c", 2.0)]).unwrap();
+        fs::rename(&tmp.vectors, &paths.vectors).unwrap();
+        paths
+    }
+
+    #[test]
+    fn interrupted_compaction_finishes_from_the_new_table() {
+        let dir = temp_dir();
+        interrupted_compaction(&dir);
+        let (_, embeddings) = VectorStore::open(&dir, "test").unwrap();
+        assert_eq!(texts(&embeddings), ["a", "c"]);
+        assert_eq!(embeddings[1].embedding, [2.0, 1.0]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn mismatched_generations_drop_the_store() {
+        let dir = temp_dir();
+        let paths = interrupted_compaction(&dir);
+        fs::remove_file(paths.with_suffix(".tmp").table).unwrap();
+        let (store, embeddings) = VectorStore::open(&dir, "test").unwrap();
+        assert!(embeddings.is_empty());
+        assert_eq!(store.dimension(), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn leftover_staging_table_is_not_promoted() {
+        let dir = temp_dir();
+        let (mut store, _) = VectorStore::open(&dir, "test").unwrap();
+        store.append("a", &[0.0, 1.0]).unwrap();
+        let (mut staging, _) = VectorStore::open_staging(&dir, "other").unwrap();
+        staging.append("b", &[1.0, 1.0]).unwrap();
+        // `discard` stopped after deleting only the vector file
+        fs::remove_file(StorePaths::new(&dir, STAGING_NAME).vectors).unwrap();
+        let (_, embeddings) = VectorStore::open(&dir, "test").unwrap();
+        assert_eq!(texts(&embeddings), ["a"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -11,46 +11,43 @@ use ratatui::{
     widgets::{Block, Borders, List, ListItem, Paragraph, ListState},
     Terminal,
 };
-use std::{
-    fs,
-    io::{self, stdout},
-    path::PathBuf,
-};
-use dirs::home_dir;
+use std::collections::HashMap;
+use std::io::{self, stdout};
+use crate::utils::notes::{self, Note, NoteFilter};
+use crate::utils::query::Query;
+use crate::utils::search::{search_notes, SearchMode};
 use crate::utils::semantic_search::SemanticSearch;
+use crate::utils::topics::find_topics;
 
-const NOTES_FILE: &str = "notes.txt";
-
-fn get_notes_path() -> PathBuf {
-    let mut path = home_dir().expect("Could not find home directory");
-    path.push("notes");
-    path.push(NOTES_FILE);
-    path
-}
+// Results shown for semantic and hybrid queries
+const SEARCH_RESULTS: usize = 10;
 
-pub fn view_notes() -> io::Result<()> {
+pub fn view_notes(filter: &NoteFilter) -> io::Result<()> {
     // Setup terminal
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
 
     // Read notes
-    let mut notes = read_notes()?;
+    let mut notes = read_notes(filter)?;
     let mut list_state = ListState::default();
     if !notes.is_empty() {
         list_state.select(Some(0));
     }
 
     // Initialize semantic search
-    let mut semantic_search = SemanticSearch::new().map_err(|e| {
-        io::Error::new(io::ErrorKind::Other, format!("Failed to initialize semantic search: {}", e))
-    })?;
+    let mut semantic_search = SemanticSearch::new()
+        .map_err(|e| io::Error::other(format!("Failed to initialize semantic search: {}", e)))?;
 
     // Search state
     let mut search_mode = false;
     let mut search_term = String::new();
     let mut filtered_notes = notes.clone();
-    let mut use_semantic_search = false;
+    let mut ranking_mode = SearchMode::default();
+    let mut query_error: Option<String> = None;
+    let mut related_to: Option<usize> = None;
+    // Topic index and label per note ID while grouping by topic
+    let mut topic_of: Option<HashMap<usize, (usize, String)>> = None;
 
     // Main event loop
     loop {
@@ -67,8 +64,13 @@ pub fn view_notes() -> io::Result<()> {
                 .split(f.size());
 
             // Header
+            let title = if filter.is_empty() {
+                "Legal Pad".to_string()
+            } else {
+                format!("Legal Pad ({})", filter.describe())
+            };
             let header = Block::default()
-                .title("Legal Pad")
+                .title(title)
                 .borders(Borders::ALL)
                 .style(Style::default().fg(Color::Yellow));
             f.render_widget(header, chunks[0]);
@@ -79,12 +81,23 @@ pub fn view_notes() -> io::Result<()> {
             } else {
                 Style::default().fg(Color::DarkGray)
             };
-            let search_type = if use_semantic_search { "Semantic Search" } else { "Regular Search" };
-            let search_bar = Paragraph::new(Text::from(format!(
-                "{}: {}",
-                search_type,
-                if search_mode { &search_term } else { "Press '/' to search, 's' to toggle search type" }
-            )))
+            let search_type = ranking_mode.label();
+            let search_text = match related_to {
+                Some(id) if !search_mode => format!("Related to #{} (Esc to go back)", id),
+                _ => format!(
+                    "{}: {}",
+                    search_type,
+                    if search_mode { &search_term } else { "Press '/' to search, 's' to change search type" }
+                ),
+            };
+            let mut search_line = vec![Span::raw(search_text)];
+            if let Some(error) = &query_error {
+                search_line.push(Span::styled(
+                    format!("  ({})", error),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            let search_bar = Paragraph::new(Line::from(search_line))
             .block(Block::default().borders(Borders::ALL))
             .style(search_style);
             f.render_widget(search_bar, chunks[1]);
@@ -93,28 +106,34 @@ pub fn view_notes() -> io::Result<()> {
             let items: Vec<ListItem> = filtered_notes
                 .iter()
                 .map(|note| {
-                    // Split the note into parts, handling the extra spaces
-                    let parts: Vec<&str> = note.split(']').collect();
-                    let timestamp = parts.get(0).map(|s| s.trim_start_matches('[').trim()).unwrap_or("");
-                    let category = parts.get(1).map(|s| s.trim_start_matches('[').trim()).unwrap_or("");
-                    let content = parts.get(2).map(|s| s.trim()).unwrap_or("");
-
+                    let id = Span::styled(
+                        format!("{:>4} ", note.id),
+                        Style::default().fg(Color::DarkGray),
+                    );
                     let timestamp = Span::styled(
-                        format!("[{}]", timestamp),
+                        format!("[{}]", note.date),
                         Style::default().fg(Color::Cyan),
                     );
                     let category = Span::styled(
-                        format!("{}]{}", category, " ".repeat(14 - category.len() - 2)), // Pad after the bracket
+                        format!("{:<12}", format!("[{}]", note.category)),
                         Style::default().fg(Color::Green),
                     );
-                    let content = Span::raw(content);
-                    ListItem::new(Line::from(vec![
-                        timestamp,
-                        Span::raw(" "),
-                        category,
-                        Span::raw(" "),
-                        content,
-                    ]))
+                    let content = Span::raw(note.text.as_str());
+                    let mut spans = vec![id, timestamp, Span::raw(" "), category, Span::raw(" ")];
+                    if let Some((_, label)) = topic_of.as_ref().and_then(|topics| topics.get(&note.id)) {
+                        spans.push(Span::styled(
+                            format!("{{{}}} ", label),
+                            Style::default().fg(Color::Magenta),
+                        ));
+                    }
+                    spans.push(content);
+                    if !note.auto_tags.is_empty() {
+                        spans.push(Span::styled(
+                            format!("  ~{}", note.auto_tags.join(" ~")),
+                            Style::default().fg(Color::DarkGray),
+                        ));
+                    }
+                    ListItem::new(Line::from(spans))
                 })
                 .collect();
 
@@ -131,10 +150,14 @@ pub fn view_notes() -> io::Result<()> {
                     Span::raw(" to navigate, "),
                     Span::styled("d", Style::default().fg(Color::Yellow)),
                     Span::raw(" to delete, "),
+                    Span::styled("r", Style::default().fg(Color::Yellow)),
+                    Span::raw(" for related notes, "),
+                    Span::styled("t", Style::default().fg(Color::Yellow)),
+                    Span::raw(" to group by topic, "),
                     Span::styled("/", Style::default().fg(Color::Yellow)),
                     Span::raw(" to search, "),
                     Span::styled("s", Style::default().fg(Color::Yellow)),
-                    Span::raw(" to toggle search type, "),
+                    Span::raw(" to change search type, "),
                     Span::styled("q", Style::default().fg(Color::Yellow)),
                     Span::raw(" to quit"),
                 ]),
@@ -145,13 +168,13 @@ pub fn view_notes() -> io::Result<()> {
 
         if let Event::Key(key) = event::read()? {
             match key.code {
-                KeyCode::Char('q') => break,
+                KeyCode::Char('q') if !search_mode => break,
                 KeyCode::Char('s') => {
                     if !search_mode {
-                        use_semantic_search = !use_semantic_search;
+                        ranking_mode = ranking_mode.next();
                     } else {
                         search_term.push('s');
-                        update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, use_semantic_search);
+                        query_error = update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, ranking_mode, filter);
                         if filtered_notes.is_empty() {
                             list_state.select(None);
                         } else {
@@ -159,34 +182,36 @@ pub fn view_notes() -> io::Result<()> {
                         }
                     }
                 }
-                KeyCode::Char('/') => {
+                KeyCode::Char('/') if !search_mode => {
                     search_mode = true;
                     search_term.clear();
+                    query_error = None;
+                    related_to = None;
                     filtered_notes = notes.clone();
                 }
                 KeyCode::Esc => {
                     search_mode = false;
                     search_term.clear();
+                    query_error = None;
+                    related_to = None;
                     filtered_notes = notes.clone();
                     if !filtered_notes.is_empty() {
                         list_state.select(Some(0));
                     }
                 }
-                KeyCode::Backspace | KeyCode::Delete => {
-                    if search_mode {
-                        search_term.pop();
-                        update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, use_semantic_search);
-                        if filtered_notes.is_empty() {
-                            list_state.select(None);
-                        } else {
-                            list_state.select(Some(0));
-                        }
+                KeyCode::Backspace | KeyCode::Delete if search_mode => {
+                    search_term.pop();
+                    query_error = update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, ranking_mode, filter);
+                    if filtered_notes.is_empty() {
+                        list_state.select(None);
+                    } else {
+                        list_state.select(Some(0));
                     }
                 }
                 KeyCode::Char(c) => {
                     if search_mode {
                         search_term.push(c);
-                        update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, use_semantic_search);
+                        query_error = update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, ranking_mode, filter);
                         if filtered_notes.is_empty() {
                             list_state.select(None);
                         } else {
@@ -194,26 +219,59 @@ pub fn view_notes() -> io::Result<()> {
                         }
                     } else if c == 'd' {
                         if let Some(selected) = list_state.selected() {
-                            let selected_note = &filtered_notes[selected];
-                            let selected_content = extract_note_content(selected_note).to_string();
-                            let original_index = notes
-                                .iter()
-                                .position(|n| n == selected_note)
-                                .or_else(|| {
-                                    notes
-                                        .iter()
-                                        .position(|n| extract_note_content(n) == selected_content)
-                                });
-                            if let Some(original_index) = original_index {
-                                delete_note(original_index)?;
-                                let _ = semantic_search.remove_note_text(&selected_content);
-                                notes = read_notes()?;
-                                update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, use_semantic_search);
-                                if filtered_notes.is_empty() {
-                                    list_state.select(None);
-                                } else if selected >= filtered_notes.len() {
-                                    list_state.select(Some(filtered_notes.len() - 1));
+                            let selected_note = filtered_notes[selected].clone();
+                            let removed = notes::delete_notes(&[selected_note.id])?;
+                            notes = read_notes(filter)?;
+                            // Keep the embedding if an identical note is still around,
+                            // including one the filter hides
+                            let remaining = notes::load_notes()?;
+                            let orphaned: Vec<String> = removed
+                                .into_iter()
+                                .map(|note| note.text)
+                                .filter(|text| !remaining.iter().any(|n| &n.text == text))
+                                .collect();
+                            let _ = semantic_search.remove_notes(&orphaned);
+                            related_to = None;
+                            // IDs shift after a delete, so regroup
+                            if topic_of.is_some() {
+                                topic_of = Some(group_by_topic(&semantic_search, &notes));
+                            }
+                            query_error = update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, ranking_mode, filter);
+                            if filtered_notes.is_empty() {
+                                list_state.select(None);
+                            } else if selected >= filtered_notes.len() {
+                                list_state.select(Some(filtered_notes.len() - 1));
+                            }
+                        }
+                    } else if c == 't' {
+                        topic_of = match topic_of {
+                            Some(_) => None,
+                            None => Some(group_by_topic(&semantic_search, &notes)),
+                        };
+                        if topic_of.is_none() {
+                            query_error = update_filtered_notes(&notes, &search_term, &mut filtered_notes, &semantic_search, ranking_mode, filter);
+                        }
+                        if !filtered_notes.is_empty() {
+                            list_state.select(Some(0));
+                        }
+                    } else if c == 'r' {
+                        if let Some(selected) = list_state.selected() {
+                            let selected_note = filtered_notes[selected].clone();
+                            match semantic_search.related(&selected_note, SEARCH_RESULTS) {
+                                Ok(results) => {
+                                    filtered_notes = results
+                                        .into_iter()
+                                        .filter_map(|result| notes.iter().find(|note| note.id == result.note.id).cloned())
+                                        .collect();
+                                    related_to = Some(selected_note.id);
+                                    query_error = None;
+                                    if filtered_notes.is_empty() {
+                                        list_state.select(None);
+                                    } else {
+                                        list_state.select(Some(0));
+                                    }
                                 }
+                                Err(e) => query_error = Some(e.to_string()),
                             }
                         }
                     }
@@ -234,6 +292,13 @@ pub fn view_notes() -> io::Result<()> {
                 }
                 _ => {}
             }
+
+            // Keep notes of the same topic together; the sort is stable, so
+            // the order within a topic is preserved. Search results keep
+            // their ranking and only show the topic labels.
+            if let Some(topics) = topic_of.as_ref().filter(|_| search_term.trim().is_empty()) {
+                filtered_notes.sort_by_key(|note| topics.get(&note.id).map_or(usize::MAX, |(topic, _)| *topic));
+            }
         }
     }
 
@@ -243,71 +308,57 @@ pub fn view_notes() -> io::Result<()> {
     Ok(())
 }
 
+/// Refresh `filtered_notes` for the current search term. On a query or
+/// search error the previous results are kept and the message is returned
+/// for the search bar.
 fn update_filtered_notes(
-    notes: &[String],
+    notes: &[Note],
     search_term: &str,
-    filtered_notes: &mut Vec<String>,
+    filtered_notes: &mut Vec<Note>,
     semantic_search: &SemanticSearch,
-    use_semantic_search: bool,
-) {
-    if search_term.is_empty() {
+    ranking_mode: SearchMode,
+    filter: &NoteFilter,
+) -> Option<String> {
+    if search_term.trim().is_empty() {
         *filtered_notes = notes.to_vec();
-    } else if use_semantic_search {
-        match semantic_search.search(search_term, 10) {
-            Ok(results) => {
-                *filtered_notes = results
-                    .into_iter()
-                    .map(|(text, _)| text)
-                    .collect();
-            }
-            Err(e) => {
-                eprintln!("Semantic search error: {}", e);
-                *filtered_notes = notes.to_vec();
-            }
+        return None;
+    }
+    // Lexical search and filter-only queries list every match; the
+    // embedding-based modes always return neighbours, so only show the
+    // closest few
+    let filter_only = Query::parse(search_term).is_ok_and(|query| query.free_text().is_empty());
+    let k = match ranking_mode {
+        SearchMode::Lexical => notes.len(),
+        _ if filter_only => notes.len(),
+        _ => SEARCH_RESULTS,
+    };
+    match search_notes(Some(semantic_search), search_term, k, filter, ranking_mode) {
+        Ok(results) => {
+            *filtered_notes = results
+                .into_iter()
+                .filter_map(|result| notes.iter().find(|note| note.id == result.note.id).cloned())
+                .collect();
+            None
         }
-    } else {
-        *filtered_notes = notes
-            .iter()
-            .filter(|note| {
-                let parts: Vec<&str> = note.split(']').collect();
-                let category = parts.get(1).map(|s| s.trim_start_matches('[').trim()).unwrap_or("");
-                let content = parts.get(2).map(|s| s.trim()).unwrap_or("");
-                category.to_lowercase().contains(&search_term.to_lowercase())
-                    || content.to_lowercase().contains(&search_term.to_lowercase())
-            })
-            .cloned()
-            .collect();
+        Err(e) => Some(e.to_string()),
     }
 }
 
-fn extract_note_content(note: &str) -> &str {
-    let mut parts = note.splitn(3, ']');
-    let _ = parts.next();
-    let _ = parts.next();
-    if let Some(content) = parts.next() {
-        content.trim()
-    } else {
-        note.trim()
-    }
+fn group_by_topic(semantic_search: &SemanticSearch, notes: &[Note]) -> HashMap<usize, (usize, String)> {
+    find_topics(semantic_search, notes, None)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, topic)| {
+            let label = topic.label;
+            topic.notes.into_iter().map(move |note| (note.id, (i, label.clone())))
+        })
+        .collect()
 }
 
-fn read_notes() -> io::Result<Vec<String>> {
-    let notes_path = get_notes_path();
-    if !notes_path.exists() {
-        return Ok(Vec::new());
-    }
-    let content = fs::read_to_string(notes_path)?;
-    let mut notes: Vec<String> = content.lines().map(String::from).collect();
+/// Notes for display, newest first.
+fn read_notes(filter: &NoteFilter) -> io::Result<Vec<Note>> {
+    let mut notes = notes::load_notes()?;
+    notes.retain(|note| filter.matches(note));
     notes.reverse();
     Ok(notes)
 }
-
-fn delete_note(index: usize) -> io::Result<()> {
-    let mut notes = read_notes()?;
-    if index < notes.len() {
-        notes.remove(index);
-        let notes_path = get_notes_path();
-        fs::write(notes_path, notes.join("\n"))?;
-    }
-    Ok(())
-} 